@@ -0,0 +1,20 @@
+use std::env;
+use std::process::Command;
+
+// Build details for `iyr --version`, so bug reports say exactly what was run.
+fn main() {
+    println!("cargo:rustc-env=IYR_TARGET={}", env::var("TARGET").unwrap_or_default());
+    println!("cargo:rustc-env=IYR_PROFILE={}", env::var("PROFILE").unwrap_or_default());
+
+    // Not every build has git, e.g. one from a crates.io tarball.
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=IYR_COMMIT={}", commit);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
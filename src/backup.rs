@@ -0,0 +1,358 @@
+use crate::fileops::{clone_file, replace_with};
+use crate::hash::{DEFAULT_READ, HashAlgo, hash_reader, raw_hash};
+use age::secrecy::SecretString;
+use regex::Regex;
+use std::fs::{self, File};
+use std::io::{self, BufReader, IsTerminal};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ----------------------
+// BACKUPS
+// ----------------------
+
+const PASSPHRASE_ENV: &str = "IYR_BACKUP_PASSPHRASE";
+
+// Whether each new backup is read back and checked against the file before
+// anything relies on it. Turned off by --verify-backups false.
+static VERIFY: AtomicBool = AtomicBool::new(true);
+
+pub fn set_verify(enabled: bool) {
+    VERIFY.store(enabled, Ordering::Relaxed);
+}
+
+// How backups are named, next to the file they back up. Placeholders:
+// {name} (the whole file name), {stem}, {ext}, {ts} (Unix time in seconds)
+// and {n} (the lowest counter, from 1, that isn't taken yet).
+#[derive(Clone, Debug)]
+pub struct BackupTemplate(String);
+
+const PLACEHOLDERS: [&str; 5] = ["name", "stem", "ext", "ts", "n"];
+
+impl Default for BackupTemplate {
+    fn default() -> Self {
+        BackupTemplate("{stem}_backup.{ext}".to_string())
+    }
+}
+
+impl BackupTemplate {
+    // --backup-suffix: appended to the whole file name, e.g. `.orig` or `~`.
+    pub fn from_suffix(suffix: &str) -> Result<Self, String> {
+        format!("{{name}}{}", suffix).parse()
+    }
+
+    fn render(&self, path: &Path, ts: u64, n: u32) -> PathBuf {
+        path.with_file_name(self.render_name(path, &ts.to_string(), &n.to_string()))
+    }
+
+    fn render_name(&self, path: &Path, ts: &str, n: &str) -> String {
+        let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("file");
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+        let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+
+        let rendered = self.0
+            .replace("{name}", name)
+            .replace("{stem}", stem)
+            .replace("{ext}", ext)
+            .replace("{ts}", ts)
+            .replace("{n}", n);
+        // A file without an extension would otherwise get a trailing dot.
+        rendered.trim_end_matches('.').to_string()
+    }
+
+    // A template without {n} gets one when its name is already taken, so an
+    // earlier backup is never overwritten: `notes_backup.2.txt`. It goes
+    // before the extension, if the template ends with one.
+    fn numbered(&self) -> BackupTemplate {
+        if self.0.contains("{n}") {
+            return self.clone();
+        }
+        match self.0.strip_suffix(".{ext}") {
+            Some(rest) => BackupTemplate(format!("{}.{{n}}.{{ext}}", rest)),
+            None => BackupTemplate(format!("{}.{{n}}", self.0)),
+        }
+    }
+
+    // Matches the name of every backup of `path` this template can make,
+    // whatever its {ts} and {n}, including those numbered to avoid a
+    // collision.
+    fn pattern(&self, path: &Path) -> Result<Regex, regex::Error> {
+        Regex::new(&format!("^(?:{})$", self.names(path)))
+    }
+
+    // Like `pattern`, for the backups of any of `paths`, encrypted or not.
+    // Tells events for iyr's own backups apart from the watched files.
+    pub fn backups_pattern(&self, paths: &[&Path]) -> Result<Regex, regex::Error> {
+        let names: Vec<String> = paths.iter().map(|path| self.names(path)).collect();
+        Regex::new(&format!("^(?:{})(?:\\.age)?$", names.join("|")))
+    }
+
+    // The unanchored alternatives behind `pattern`.
+    fn names(&self, path: &Path) -> String {
+        const NUMBER: &str = "\u{0}";
+        [self.clone(), self.numbered()]
+            .iter()
+            .map(|template| regex::escape(&template.render_name(path, NUMBER, NUMBER)).replace(NUMBER, "[0-9]+"))
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+}
+
+impl FromStr for BackupTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        while let Some(open) = rest.find('{') {
+            let close = rest[open..].find('}').ok_or("unclosed '{' in backup template")? + open;
+            let placeholder = &rest[open + 1..close];
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!("unknown placeholder {{{}}} in backup template", placeholder));
+            }
+            rest = &rest[close + 1..];
+        }
+
+        if !s.contains("{name}") && !s.contains("{stem}") {
+            return Err("backup template needs {name} or {stem}, or backups of different files would collide".to_string());
+        }
+        if s.contains('/') || s.contains(std::path::MAIN_SEPARATOR) {
+            return Err("backup template must be a file name, not a path".to_string());
+        }
+
+        let template = BackupTemplate(s.to_string());
+        let sample = Path::new("notes.txt");
+        if template.render(sample, 0, 1) == sample {
+            return Err("backup template names the file itself, so the backup would overwrite it".to_string());
+        }
+        Ok(template)
+    }
+}
+
+// Backs up `src` next to the path the user gave, named by `template`. With a
+// passphrase (--encrypt-backups) the backup is an age file, `<name>.age`, so
+// no plaintext copy is left behind. With `dedupe` (--dedupe-backups), no new
+// backup is made if the most recent one already has the same content.
+// Returns where the backup went.
+pub fn create_backup(
+    src: &Path,
+    input: &str,
+    template: &BackupTemplate,
+    passphrase: Option<&SecretString>,
+    dedupe: bool,
+) -> io::Result<PathBuf> {
+    if dedupe
+        && passphrase.is_none()
+        && let Some(latest) = latest_backup(Path::new(input), template)?
+        && same_content(src, &latest)?
+    {
+        log_out!("   Same content as the last backup {:?}. Not backing up again.", crate::logging::shown(&latest));
+        return Ok(latest);
+    }
+
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let backup_path = |template: &BackupTemplate, n| {
+        let mut path = template.render(Path::new(input), ts, n);
+        if passphrase.is_some() {
+            path.as_mut_os_string().push(".age");
+        }
+        path
+    };
+
+    // {n} counts up from 1. A template without it is numbered from 2 once
+    // its plain name is taken, by an earlier backup or one made within the
+    // same second.
+    let mut path = backup_path(template, 1);
+    if path.exists() {
+        let numbered = template.numbered();
+        let mut n = 2;
+        path = backup_path(&numbered, n);
+        while path.exists() {
+            n += 1;
+            path = backup_path(&numbered, n);
+        }
+    }
+
+    match passphrase {
+        None => {
+            clone_file(src, &path)?;
+        }
+        Some(passphrase) => {
+            replace_with(&path, |tmp| encrypt(src, tmp, passphrase))?;
+        }
+    }
+    if VERIFY.load(Ordering::Relaxed) {
+        verify(src, &path, passphrase)?;
+    }
+    Ok(path)
+}
+
+// Errors unless `backup` holds exactly what `src` does, decrypting it first
+// if it's encrypted. A bad backup is removed, so it can't be mistaken for a
+// good one, and the error stops whatever was about to overwrite `src`.
+fn verify(src: &Path, backup: &Path, passphrase: Option<&SecretString>) -> io::Result<()> {
+    let backed_up = match passphrase {
+        None => raw_hash(backup, HashAlgo::Sha256)?,
+        Some(passphrase) => hash_reader(decrypting(backup, passphrase)?, HashAlgo::Sha256, DEFAULT_READ)?,
+    };
+    if backed_up == raw_hash(src, HashAlgo::Sha256)? {
+        return Ok(());
+    }
+    let _ = fs::remove_file(backup);
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("backup {:?} doesn't match {:?} after writing it (--verify-backups)", backup, src),
+    ))
+}
+
+// The most recently modified backup of `path` under `template`, if any.
+fn latest_backup(path: &Path, template: &BackupTemplate) -> io::Result<Option<PathBuf>> {
+    let pattern = template.pattern(path).map_err(io::Error::other)?;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut latest: Option<(SystemTime, PathBuf)> = None;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_name().to_str().is_some_and(|name| pattern.is_match(name)) {
+            continue;
+        }
+        let modified = entry.metadata()?.modified()?;
+        if latest.as_ref().is_none_or(|(newest, _)| modified > *newest) {
+            latest = Some((modified, entry.path()));
+        }
+    }
+    Ok(latest.map(|(_, path)| path))
+}
+
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    Ok(raw_hash(a, HashAlgo::Sha256)? == raw_hash(b, HashAlgo::Sha256)?)
+}
+
+// `restore`: puts a backup back over `target`, decrypting it first if it's
+// an age file.
+pub fn restore_backup(backup: &Path, target: &Path) -> io::Result<u64> {
+    if backup.extension().is_some_and(|ext| ext == "age") {
+        let passphrase = read_passphrase()?;
+        replace_with(target, |tmp| decrypt(backup, tmp, &passphrase))
+    } else {
+        clone_file(backup, target)
+    }
+}
+
+// Taken from IYR_BACKUP_PASSPHRASE so it can be scripted, or asked for on
+// the terminal.
+pub fn read_passphrase() -> io::Result<SecretString> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(SecretString::from(passphrase));
+    }
+
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::other(format!(
+            "no backup passphrase: set {} or run from a terminal",
+            PASSPHRASE_ENV
+        )));
+    }
+
+    rpassword::prompt_password("🔑 Backup passphrase: ").map(SecretString::from)
+}
+
+fn encrypt(src: &Path, dst: &Path, passphrase: &SecretString) -> io::Result<()> {
+    let encryptor = age::Encryptor::with_user_passphrase(passphrase.clone());
+    let mut output = encryptor.wrap_output(File::create(dst)?)?;
+    io::copy(&mut File::open(src)?, &mut output)?;
+    output.finish()?.sync_all()
+}
+
+fn decrypting(src: &Path, passphrase: &SecretString) -> io::Result<impl io::Read> {
+    let decryptor = age::Decryptor::new(BufReader::new(File::open(src)?)).map_err(io::Error::other)?;
+    let identity = age::scrypt::Identity::new(passphrase.clone());
+    decryptor
+        .decrypt(iter::once(&identity as &dyn age::Identity))
+        .map_err(io::Error::other)
+}
+
+fn decrypt(src: &Path, dst: &Path, passphrase: &SecretString) -> io::Result<()> {
+    let mut input = decrypting(src, passphrase)?;
+    io::copy(&mut input, &mut File::create(dst)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered(template: &str, file: &str) -> String {
+        let template: BackupTemplate = template.parse().unwrap();
+        template.render_name(Path::new(file), "1700000000", "3")
+    }
+
+    #[test]
+    fn renders_every_placeholder() {
+        assert_eq!(rendered("{stem}_backup.{ext}", "notes.txt"), "notes_backup.txt");
+        assert_eq!(rendered("{name}.orig", "notes.txt"), "notes.txt.orig");
+        assert_eq!(rendered("{stem}.{ts}.{n}.bak.{ext}", "notes.txt"), "notes.1700000000.3.bak.txt");
+    }
+
+    #[test]
+    fn no_trailing_dot_without_an_extension() {
+        assert_eq!(rendered("{stem}_backup.{ext}", "Makefile"), "Makefile_backup");
+    }
+
+    #[test]
+    fn suffix_is_appended_to_the_name() {
+        let template = BackupTemplate::from_suffix("~").unwrap();
+        assert_eq!(template.render_name(Path::new("notes.txt"), "0", "1"), "notes.txt~");
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for bad in ["{stem}.{nope}", "{stem", "backup.{ext}", "old/{name}", "{stem}.{ext}", "{name}"] {
+            assert!(bad.parse::<BackupTemplate>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn pattern_matches_every_backup_it_can_make() {
+        let template: BackupTemplate = "{stem}.{ts}.bak.{ext}".parse().unwrap();
+        let pattern = template.pattern(Path::new("notes.txt")).unwrap();
+        assert!(pattern.is_match("notes.1700000000.bak.txt"));
+        // Numbered after a collision within the same second.
+        assert!(pattern.is_match("notes.1700000000.bak.2.txt"));
+        assert!(!pattern.is_match("notes.txt"));
+        assert!(!pattern.is_match("other.1700000000.bak.txt"));
+    }
+
+    #[test]
+    fn backups_pattern_covers_encrypted_backups() {
+        let template = BackupTemplate::default();
+        let pattern = template.backups_pattern(&[Path::new("a.txt"), Path::new("b.md")]).unwrap();
+        for name in ["a_backup.txt", "a_backup.2.txt", "b_backup.md.age"] {
+            assert!(pattern.is_match(name), "{} didn't match", name);
+        }
+        assert!(!pattern.is_match("a.txt"));
+        assert!(!pattern.is_match("c_backup.txt"));
+    }
+
+    #[test]
+    fn taken_name_gets_a_number_instead_of_being_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        let input = file.to_string_lossy().into_owned();
+        let template = BackupTemplate::default();
+
+        fs::write(&file, "first\n").unwrap();
+        let first = create_backup(&file, &input, &template, None, false).unwrap();
+        fs::write(&file, "second\n").unwrap();
+        let second = create_backup(&file, &input, &template, None, false).unwrap();
+
+        assert_eq!(first, dir.path().join("notes_backup.txt"));
+        assert_eq!(second, dir.path().join("notes_backup.2.txt"));
+        assert_eq!(fs::read_to_string(first).unwrap(), "first\n");
+        assert_eq!(fs::read_to_string(second).unwrap(), "second\n");
+    }
+}
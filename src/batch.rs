@@ -0,0 +1,134 @@
+use crate::hash::{HashOptions, compute_hash};
+use crate::preflight::{Rules, Totals};
+use crate::validate::special_file_kind;
+use crate::{EXIT_DIFFER, EXIT_IO};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+// ----------------------
+// BATCH VERIFY
+// ----------------------
+
+enum Outcome {
+    Identical,
+    Differ,
+    Error(String),
+}
+
+// --batch: checks every pair in a list and reports on all of them, instead
+// of stopping at the first mismatch. One pair per line, split shell-style so
+// paths with spaces can be quoted; blank lines and `#` comments are skipped.
+// `-` reads the list from stdin. With --preflight (`rules`), each pair also
+// says what a sync would do to it. Returns the exit code.
+pub fn run(list: &Path, options: &HashOptions, rules: Option<&Rules>) -> io::Result<i32> {
+    let reader: Box<dyn BufRead> = if list == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(list)?))
+    };
+
+    let (mut identical, mut differ, mut errors) = (0, 0, 0);
+    let mut totals = Totals::default();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (path_a, path_b) = match parse_pair(line) {
+            Ok(pair) => pair,
+            Err(e) => {
+                log_err!("❌ Line {}: {}", number + 1, e);
+                errors += 1;
+                continue;
+            }
+        };
+
+        let mut outcome = check_pair(&path_a, &path_b, options);
+        let mut plan = None;
+        if let Some(rules) = rules
+            && !matches!(outcome, Outcome::Error(_))
+        {
+            match (fs::metadata(&path_a), fs::metadata(&path_b)) {
+                (Ok(a), Ok(b)) => plan = Some(rules.plan(matches!(outcome, Outcome::Differ), a.len(), b.len())),
+                (Err(e), _) | (_, Err(e)) => outcome = Outcome::Error(e.to_string()),
+            }
+        }
+
+        let (status, detail) = match &outcome {
+            Outcome::Identical => ("ok", None),
+            Outcome::Differ => ("DIFFER", None),
+            Outcome::Error(e) => ("ERROR", Some(e.as_str())),
+        };
+        match (detail, &plan, rules) {
+            (_, Some(action), Some(rules)) if !matches!(outcome, Outcome::Identical) => {
+                log_out!("{:<7} {:?} <=> {:?}: would {}", status, path_a, path_b, action.describe(rules.conflict))
+            }
+            (Some(detail), _, _) => log_out!("{:<7} {:?} <=> {:?} ({})", status, path_a, path_b, detail),
+            _ => log_out!("{:<7} {:?} <=> {:?}", status, path_a, path_b),
+        }
+        let mut event = serde_json::json!({
+            "type": "batch_pair",
+            "a": path_a,
+            "b": path_b,
+            "status": status.to_lowercase(),
+            "detail": detail,
+        });
+        if let Some(action) = &plan {
+            event["plan"] = action.to_json();
+            totals.add(action);
+        }
+        crate::logging::emit_json(event);
+
+        match outcome {
+            Outcome::Identical => identical += 1,
+            Outcome::Differ => differ += 1,
+            Outcome::Error(_) => errors += 1,
+        }
+    }
+
+    log_out!("📋 {} identical, {} differ, {} errors", identical, differ, errors);
+    crate::logging::emit_json(serde_json::json!({
+        "type": "batch_summary",
+        "identical": identical,
+        "differ": differ,
+        "errors": errors,
+    }));
+    if rules.is_some() {
+        totals.print();
+        crate::logging::emit_json(totals.to_json());
+        // What counts is whether a sync would go through.
+        return Ok(if errors > 0 { EXIT_IO } else { totals.exit_code() });
+    }
+
+    // A pair that couldn't be checked is as much a failure as one that
+    // differs; it's reported as the I/O error it usually is.
+    Ok(if errors > 0 { EXIT_IO } else if differ > 0 { EXIT_DIFFER } else { 0 })
+}
+
+fn parse_pair(line: &str) -> Result<(PathBuf, PathBuf), String> {
+    let words = shell_words::split(line).map_err(|e| e.to_string())?;
+    match <[String; 2]>::try_from(words) {
+        Ok([a, b]) => Ok((PathBuf::from(a), PathBuf::from(b))),
+        Err(words) => Err(format!("expected two paths, found {}", words.len())),
+    }
+}
+
+fn check_pair(path_a: &Path, path_b: &Path, options: &HashOptions) -> Outcome {
+    let hash = |path: &Path| -> Result<_, String> {
+        // Hashing a FIFO or device would block or never finish.
+        if let Some(kind) = special_file_kind(path).map_err(|e| format!("{}: {}", path.display(), e))? {
+            return Err(format!("{} is {}", path.display(), kind));
+        }
+        compute_hash(path, options).map_err(|e| format!("{}: {}", path.display(), e))
+    };
+
+    match (hash(path_a), hash(path_b)) {
+        (Ok(a), Ok(b)) if a == b => Outcome::Identical,
+        (Ok(_), Ok(_)) => Outcome::Differ,
+        (Err(e), _) | (_, Err(e)) => Outcome::Error(e),
+    }
+}
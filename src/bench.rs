@@ -0,0 +1,43 @@
+use crate::hash::{self, BLAKE3_READ, DEFAULT_READ, HashAlgo};
+use crate::logging::shown;
+use clap::ValueEnum;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// ----------------------
+// HASH BENCHMARK
+// ----------------------
+
+// `iyr bench-hash FILE` times hashing FILE with every algorithm and a few
+// read sizes, to help pick --hash-algo. Nothing in a normal run uses it.
+// Each combination runs a few times and the fastest run counts, so the
+// first one warming the page cache doesn't skew the numbers.
+
+const READ_SIZES: &[usize] = &[DEFAULT_READ, 64 * 1024, BLAKE3_READ, 8 * 1024 * 1024];
+const RUNS: usize = 3;
+
+pub fn run(path: &Path) -> io::Result<()> {
+    let len = fs::metadata(path)?.len();
+    log_out!("⏱️ Hashing {:?} ({} bytes), best of {} runs:", shown(path), len, RUNS);
+
+    for &algo in HashAlgo::value_variants() {
+        for &read_size in READ_SIZES {
+            let mut best = hash::time_hash(path, algo, read_size)?;
+            for _ in 1..RUNS {
+                best = best.min(hash::time_hash(path, algo, read_size)?);
+            }
+            let throughput = len as f64 / best.as_secs_f64().max(f64::EPSILON) / 1_000_000.0;
+            let default = read_size == if algo == HashAlgo::Blake3 { BLAKE3_READ } else { DEFAULT_READ };
+            log_out!(
+                "   {:<7} {:>5} KiB reads: {:>9.1} MB/s ({:.2} ms){}",
+                algo.to_string(),
+                read_size / 1024,
+                throughput,
+                best.as_secs_f64() * 1000.0,
+                if default { "  <- what iyr uses" } else { "" }
+            );
+        }
+    }
+    Ok(())
+}
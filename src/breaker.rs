@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// ----------------------
+// EVENT STORM BREAKER
+// ----------------------
+
+// How long the rate has to stay above the limit before syncing pauses.
+const WINDOW: Duration = Duration::from_secs(5);
+// How long syncing stays paused once tripped.
+const COOLDOWN: Duration = Duration::from_secs(10);
+
+// Pauses syncing when events for the pair keep arriving faster than
+// --max-events-per-sec, e.g. when another tool is fighting iyr over a file.
+// Resumes once a cooldown has passed and the rate is back under the limit.
+pub struct CircuitBreaker {
+    max_per_sec: u32,
+    recent: VecDeque<Instant>,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(max_per_sec: u32) -> Self {
+        CircuitBreaker { max_per_sec, recent: VecDeque::new(), open_until: None }
+    }
+
+    // Records `count` events. Returns true if this trips the breaker.
+    pub fn record(&mut self, count: usize, now: Instant) -> bool {
+        self.recent.extend(std::iter::repeat_n(now, count));
+        self.prune(now);
+
+        if self.open_until.is_none() && self.storming() {
+            self.open_until = Some(now + COOLDOWN);
+            return true;
+        }
+        false
+    }
+
+    // When the breaker is next due to be checked for closing, if it's open.
+    pub fn open_until(&self) -> Option<Instant> {
+        self.open_until
+    }
+
+    // Whether syncing is paused. Closes the breaker once the cooldown is
+    // over and the storm has died down, or extends it if it hasn't.
+    pub fn is_open(&mut self, now: Instant) -> bool {
+        let Some(until) = self.open_until else {
+            return false;
+        };
+        if now < until {
+            return true;
+        }
+
+        self.prune(now);
+        if self.storming() {
+            self.open_until = Some(now + COOLDOWN);
+            return true;
+        }
+
+        self.open_until = None;
+        false
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while self.recent.front().is_some_and(|t| now.duration_since(*t) > WINDOW) {
+            self.recent.pop_front();
+        }
+    }
+
+    fn storming(&self) -> bool {
+        self.recent.len() as u64 > self.max_per_sec as u64 * WINDOW.as_secs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stays_closed_under_the_limit() {
+        let mut breaker = CircuitBreaker::new(10);
+        let now = Instant::now();
+        // Exactly the limit, averaged over the window.
+        assert!(!breaker.record(50, now));
+        assert!(!breaker.is_open(now));
+        assert_eq!(breaker.open_until(), None);
+    }
+
+    #[test]
+    fn trips_once_over_the_limit() {
+        let mut breaker = CircuitBreaker::new(10);
+        let now = Instant::now();
+        assert!(breaker.record(51, now));
+        assert!(breaker.is_open(now));
+        assert_eq!(breaker.open_until(), Some(now + COOLDOWN));
+        // Already open: further events don't trip it again.
+        assert!(!breaker.record(100, now));
+    }
+
+    #[test]
+    fn closes_after_the_cooldown_once_the_storm_is_over() {
+        let mut breaker = CircuitBreaker::new(10);
+        let now = Instant::now();
+        breaker.record(51, now);
+        assert!(breaker.is_open(now + COOLDOWN - Duration::from_secs(1)));
+        // By then the burst has left the window.
+        assert!(!breaker.is_open(now + COOLDOWN));
+        assert_eq!(breaker.open_until(), None);
+    }
+
+    #[test]
+    fn stays_open_while_the_storm_goes_on() {
+        let mut breaker = CircuitBreaker::new(10);
+        let now = Instant::now();
+        breaker.record(51, now);
+        let later = now + COOLDOWN;
+        breaker.record(51, later);
+        assert!(breaker.is_open(later));
+        assert_eq!(breaker.open_until(), Some(later + COOLDOWN));
+    }
+
+    #[test]
+    fn old_events_leave_the_window() {
+        let mut breaker = CircuitBreaker::new(10);
+        let now = Instant::now();
+        breaker.record(50, now);
+        assert!(!breaker.record(50, now + WINDOW + Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,103 @@
+use clap::ValueEnum;
+use flate2::Compression as Level;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// ----------------------
+// COMPRESSED FILES
+// ----------------------
+
+// --decompress-b: B is stored compressed. Everything that looks at its
+// content (hashing, the text check, syncing from it) reads it through a
+// decoder, and everything written to it is compressed again, so A and B are
+// compared and kept in sync by their plain content. Both go through streams,
+// so hashing a large compressed file doesn't hold it in memory.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    /// gzip, as written by `gzip` (`.gz`)
+    Gzip,
+}
+
+impl Compression {
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+        }
+    }
+}
+
+// The compressed path and its format. None when nothing is compressed.
+static COMPRESSED: Mutex<Option<(PathBuf, Compression)>> = Mutex::new(None);
+
+pub fn set_compressed(path: &Path, compression: Compression) {
+    *COMPRESSED.lock().unwrap() = Some((path.to_path_buf(), compression));
+}
+
+fn compression(path: &Path) -> Option<Compression> {
+    match &*COMPRESSED.lock().unwrap() {
+        Some((compressed, compression)) if compressed == path => Some(*compression),
+        _ => None,
+    }
+}
+
+pub fn is_compressed(path: &Path) -> bool {
+    compression(path).is_some()
+}
+
+// The file's name without the compression extension, e.g. `notes.txt` for
+// a compressed `notes.txt.gz`. Other names are returned as they are.
+pub fn plain_name<'a>(path: &Path, name: &'a str) -> &'a str {
+    let Some(compression) = compression(path) else {
+        return name;
+    };
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if ext.eq_ignore_ascii_case(compression.extension()) => stem,
+        _ => name,
+    }
+}
+
+// `file`, opened from `path`, as a stream of its plain content.
+pub fn decoder(file: File, path: &Path) -> Box<dyn Read> {
+    match compression(path) {
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(file)),
+        None => Box::new(file),
+    }
+}
+
+pub fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    Ok(decoder(File::open(path)?, path))
+}
+
+// Reads the file's plain content.
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    open(path)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+// The size of the file's plain content. For a compressed file that means
+// decoding it, as a stream, so sizes compare like for like with the other
+// side.
+pub fn len(path: &Path) -> io::Result<u64> {
+    match compression(path) {
+        Some(_) => io::copy(&mut open(path)?, &mut io::sink()),
+        None => Ok(std::fs::metadata(path)?.len()),
+    }
+}
+
+// Writes plain `content` to `file`, compressed the way `path` is stored.
+pub fn write(path: &Path, mut file: File, mut content: impl Read) -> io::Result<()> {
+    match compression(path) {
+        Some(Compression::Gzip) => {
+            let mut encoder = GzEncoder::new(file, Level::default());
+            io::copy(&mut content, &mut encoder)?;
+            encoder.finish()?.flush()
+        }
+        None => io::copy(&mut content, &mut file).map(|_| ()),
+    }
+}
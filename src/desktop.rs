@@ -0,0 +1,60 @@
+use std::path::Path;
+
+// ----------------------
+// DESKTOP NOTIFICATIONS
+// ----------------------
+
+// --notify-on-conflict: an overwrite in a background instance otherwise only
+// shows up in a log nobody is reading. Not reaching the notification service
+// is logged and never stops the sync.
+pub fn notify_conflict(winner: &Path, overwritten: &Path, backup: &Path) {
+    let body = format!(
+        "{} was overwritten with {}.\nIts old content was backed up to {}.",
+        overwritten.display(),
+        winner.display(),
+        backup.display()
+    );
+    show("iyr: conflict resolved", &body);
+}
+
+// --conflict keep-both: the originals are left alone, so this is about
+// where the other versions went.
+pub fn notify_kept_both(copy_a: &Path, copy_b: &Path) {
+    let body = format!(
+        "Both files were kept. Each one's content was copied next to the other:\n{}\n{}",
+        copy_a.display(),
+        copy_b.display()
+    );
+    show("iyr: conflict, both kept", &body);
+}
+
+// --read-only-peer: somebody changed the mirror.
+pub fn notify_peer_edited(peer: &Path, what: &str, reverted: bool) {
+    let body = match reverted {
+        true => format!("{} was {}. It was reverted.", peer.display(), what),
+        false => format!("{} was {}. It was left as it is.", peer.display(), what),
+    };
+    show("iyr: read-only peer changed", &body);
+}
+
+// --max-conflicts: syncing has stopped, which needs someone to look at it.
+pub fn notify_halted(path_a: &Path, path_b: &Path, conflicts: u64) {
+    let body = format!(
+        "{} and {} conflicted {} times, so syncing them has stopped.",
+        path_a.display(),
+        path_b.display(),
+        conflicts
+    );
+    show("iyr: syncing halted", &body);
+}
+
+fn show(summary: &str, body: &str) {
+    let result = notify_rust::Notification::new()
+        .appname("iyr")
+        .summary(summary)
+        .body(body)
+        .show();
+    if let Err(e) = result {
+        log_err!("⚠️ Warning: Could not show a desktop notification: {}", e);
+    }
+}
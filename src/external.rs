@@ -0,0 +1,48 @@
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::str::FromStr;
+
+// ----------------------
+// EXTERNAL COMMANDS
+// ----------------------
+
+// A user-supplied command line such as `meld {a} {b}`. It is split into
+// arguments once, shell-style, and placeholders are substituted per argument
+// when it runs, so paths with spaces never need quoting.
+#[derive(Clone, Debug)]
+pub struct CommandTemplate {
+    args: Vec<String>,
+}
+
+impl CommandTemplate {
+    pub fn to_command(&self, substitutions: &[(&str, &Path)]) -> Command {
+        let mut args = self.args.iter().map(|arg| {
+            let mut arg = arg.clone();
+            for (name, path) in substitutions {
+                arg = arg.replace(&format!("{{{}}}", name), &path.to_string_lossy());
+            }
+            arg
+        });
+
+        // `from_str` guarantees at least one argument.
+        let mut command = Command::new(args.next().unwrap_or_default());
+        command.args(args);
+        command
+    }
+
+    pub fn run(&self, substitutions: &[(&str, &Path)]) -> std::io::Result<ExitStatus> {
+        self.to_command(substitutions).status()
+    }
+}
+
+impl FromStr for CommandTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = shell_words::split(s).map_err(|e| e.to_string())?;
+        if args.is_empty() {
+            return Err("command is empty".to_string());
+        }
+        Ok(CommandTemplate { args })
+    }
+}
@@ -0,0 +1,343 @@
+use crate::compress;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+// ----------------------
+// FILE OPERATIONS
+// ----------------------
+
+// Whether writes are flushed to stable storage before they count as done.
+// Turned off by --no-fsync.
+static FSYNC: AtomicBool = AtomicBool::new(true);
+
+pub fn set_fsync(enabled: bool) {
+    FSYNC.store(enabled, Ordering::Relaxed);
+}
+
+// --track-inode: the inode each write left at its path, until it's taken,
+// so iyr's own renames can be told apart from someone else's. None while
+// nothing is tracked.
+static WRITTEN: Mutex<Option<HashMap<PathBuf, u64>>> = Mutex::new(None);
+
+pub fn set_track_inodes(enabled: bool) {
+    *WRITTEN.lock().unwrap() = enabled.then(HashMap::new);
+}
+
+// The inode iyr last wrote to `path`, if it wrote there since the last call.
+pub fn take_written_inode(path: &Path) -> Option<u64> {
+    WRITTEN.lock().unwrap().as_mut()?.remove(path)
+}
+
+// The modification time each write left at its path, until it's taken, so
+// the event for iyr's own write isn't mistaken for a touch.
+static WRITTEN_MTIME: Mutex<Option<HashMap<PathBuf, SystemTime>>> = Mutex::new(None);
+
+pub fn take_written_mtime(path: &Path) -> Option<SystemTime> {
+    WRITTEN_MTIME.lock().unwrap().as_mut()?.remove(path)
+}
+
+fn note_written(path: &Path, tmp: &Path) {
+    if let Some(written) = WRITTEN.lock().unwrap().as_mut()
+        && let Some(ino) = inode(tmp)
+    {
+        written.insert(path.to_path_buf(), ino);
+    }
+    // A rename keeps the modification time.
+    if let Some(mtime) = modified(tmp) {
+        WRITTEN_MTIME.lock().unwrap().get_or_insert_with(HashMap::new).insert(path.to_path_buf(), mtime);
+    }
+}
+
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Scratch file next to `dst`, on the same filesystem so it can be renamed
+// over `dst` in one step.
+pub fn temp_path(dst: &Path) -> PathBuf {
+    let name = dst.file_name().and_then(|s| s.to_str()).unwrap_or("file");
+    dst.with_file_name(format!(".{}.iyr-tmp", name))
+}
+
+// Runs `write` against a fresh temp file and renames it over `dst`, keeping
+// `dst`'s permissions if it existed. The temp file is flushed to disk before
+// the rename, and the rename itself afterwards, so after a crash `dst` holds
+// either its old content or the new content, never a mix. Without fsync the
+// rename still can't leave a mix, but a sync reported just before a power
+// loss may not survive it. The temp file is removed on failure.
+//
+// If `dst` is a symlink (--no-follow-symlinks), it's the link's target that
+// is replaced, so the link stays a link.
+pub fn replace_with(dst: &Path, write: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<u64> {
+    stage_with(dst, write)?.commit()
+}
+
+// Where a write to `dst` lands: the target, if `dst` is a symlink that
+// points at something.
+fn write_target(dst: &Path) -> PathBuf {
+    match fs::symlink_metadata(dst) {
+        Ok(meta) if meta.file_type().is_symlink() => fs::canonicalize(dst).unwrap_or_else(|_| dst.to_path_buf()),
+        _ => dst.to_path_buf(),
+    }
+}
+
+// A temp file that's written and flushed, waiting to be renamed over its
+// target. Staging several files before committing any of them means a
+// failed write leaves all of them as they were. Dropped uncommitted, it's
+// removed.
+pub struct Staged {
+    // The path as iyr knows it, which its own writes are recorded under.
+    dst: PathBuf,
+    // What the rename replaces: `dst`, or the target of a symlink there.
+    target: PathBuf,
+    tmp: PathBuf,
+}
+
+// The first half of `replace_with`: everything up to the rename.
+pub fn stage_with(dst: &Path, write: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<Staged> {
+    stage_into(dst, write_target(dst), write)
+}
+
+fn stage_into(dst: &Path, target: PathBuf, write: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<Staged> {
+    let tmp = temp_path(&target);
+    let staged = Staged { dst: dst.to_path_buf(), target, tmp };
+    let _ = fs::remove_file(&staged.tmp);
+
+    write(&staged.tmp)?;
+    if FSYNC.load(Ordering::Relaxed) {
+        File::open(&staged.tmp)?.sync_all()?;
+    }
+    if let Ok(meta) = fs::metadata(dst) {
+        fs::set_permissions(&staged.tmp, meta.permissions())?;
+    }
+    Ok(staged)
+}
+
+// Replaces the symlink at `path` with a regular file holding its target's
+// content (--dereference-on-write false). The target is left as it was.
+pub fn dereference(path: &Path) -> io::Result<u64> {
+    stage_into(path, path.to_path_buf(), |tmp| reflink_copy::reflink_or_copy(path, tmp).map(|_| ()))?.commit()
+}
+
+// `content` staged for `dst`, compressed if `dst` is stored that way.
+pub fn stage(dst: &Path, content: &[u8]) -> io::Result<Staged> {
+    stage_with(dst, |tmp| compress::write(dst, File::create(tmp)?, content))
+}
+
+impl Staged {
+    // Renames the temp file over `dst`. Returns the size of `dst`'s new
+    // plain content.
+    pub fn commit(self) -> io::Result<u64> {
+        note_written(&self.dst, &self.tmp);
+        fs::rename(&self.tmp, &self.target)?;
+        if FSYNC.load(Ordering::Relaxed) {
+            sync_parent(&self.target);
+        }
+        compress::len(&self.dst)
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        // A no-op once committed: the rename moved the file away.
+        let _ = fs::remove_file(&self.tmp);
+    }
+}
+
+// Makes a rename in `path`'s directory durable. Best effort: not every
+// platform lets a directory be opened for this.
+fn sync_parent(path: &Path) {
+    if let Some(parent) = path.parent()
+        && let Ok(dir) = File::open(parent)
+    {
+        let _ = dir.sync_all();
+    }
+}
+
+// Replaces `dst` with `content`, compressed if `dst` is stored that way;
+// see `replace_with`.
+pub fn write_atomic(dst: &Path, content: &[u8]) -> io::Result<u64> {
+    stage(dst, content)?.commit()
+}
+
+// Copies `src` over `dst` as a copy-on-write clone where the filesystem
+// supports it (APFS, Btrfs, XFS), so backups of large files cost next to
+// nothing. Falls back to a normal copy when cloning isn't possible, e.g.
+// across devices. A clone needs a fresh destination, hence `replace_with`.
+pub fn clone_file(src: &Path, dst: &Path) -> io::Result<u64> {
+    replace_with(dst, |tmp| reflink_copy::reflink_or_copy(src, tmp).map(|_| ()))
+}
+
+// Copies one file's content over another's: a clone, unless either one is
+// compressed (--decompress-b), which is decompressed or compressed again on
+// the way. Backups use `clone_file`, so they keep the bytes as stored.
+pub fn copy_over(src: &Path, dst: &Path) -> io::Result<u64> {
+    if compress::is_compressed(src) || compress::is_compressed(dst) {
+        return replace_with(dst, |tmp| compress::write(dst, File::create(tmp)?, compress::open(src)?));
+    }
+    clone_file(src, dst)
+}
+
+// Makes `dst` a hard link to `src`, replacing whatever was there in one
+// rename, like `replace_with`.
+pub fn hard_link_over(src: &Path, dst: &Path) -> io::Result<()> {
+    let tmp = temp_path(dst);
+    let _ = fs::remove_file(&tmp);
+    fs::hard_link(src, &tmp)?;
+    if let Err(e) = fs::rename(&tmp, dst) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    if FSYNC.load(Ordering::Relaxed) {
+        sync_parent(dst);
+    }
+    Ok(())
+}
+
+// Like `fs::canonicalize`, but a symlink at the end of the path is kept, not
+// resolved. Only the directory is canonicalized, so events in it still match
+// the path. Fails if nothing, not even a dangling link, is at the path.
+pub fn absolutize(path: &Path) -> io::Result<PathBuf> {
+    fs::symlink_metadata(path)?;
+    let path = std::path::absolute(path)?;
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok(fs::canonicalize(parent)?.join(name)),
+        _ => Ok(path),
+    }
+}
+
+// Whether both paths are the same file on disk, i.e. hard links to one
+// inode. Always false where that can't be told.
+#[cfg(unix)]
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn same_file(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+// The file's inode number, or None if it's missing or that can't be told.
+#[cfg(unix)]
+pub fn inode(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| m.ino())
+}
+
+#[cfg(not(unix))]
+pub fn inode(_path: &Path) -> Option<u64> {
+    None
+}
+
+// A fresh directory under the system temp dir, with a random name that
+// starts with `prefix`. On Unix only its owner can get into it. Removed
+// when dropped.
+#[cfg(unix)]
+pub fn private_temp_dir(prefix: &str) -> io::Result<tempfile::TempDir> {
+    use std::os::unix::fs::PermissionsExt;
+    tempfile::Builder::new().prefix(prefix).permissions(fs::Permissions::from_mode(0o700)).tempdir()
+}
+
+#[cfg(not(unix))]
+pub fn private_temp_dir(prefix: &str) -> io::Result<tempfile::TempDir> {
+    tempfile::Builder::new().prefix(prefix).tempdir()
+}
+
+// How permissions are shown in log lines: octal mode bits on Unix, the
+// read-only flag elsewhere.
+#[cfg(unix)]
+pub fn describe_permissions(perms: &fs::Permissions) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    format!("{:o}", perms.mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn describe_permissions(perms: &fs::Permissions) -> String {
+    if perms.readonly() { "read-only" } else { "writable" }.to_string()
+}
+
+// Copies every extended attribute of `src` onto `dst`. All of them are
+// attempted; the first failure is returned. A no-op where xattrs aren't
+// supported.
+#[cfg(unix)]
+pub fn copy_xattrs(src: &Path, dst: &Path) -> io::Result<()> {
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(());
+    }
+
+    let mut result = Ok(());
+    for name in xattr::list(src)? {
+        let copied = match xattr::get(src, &name)? {
+            Some(value) => xattr::set(dst, &name, &value),
+            None => Ok(()),
+        };
+        if let Err(e) = copied
+            && result.is_ok()
+        {
+            result = Err(io::Error::new(e.kind(), format!("{}: {}", name.to_string_lossy(), e)));
+        }
+    }
+    result
+}
+
+#[cfg(not(unix))]
+pub fn copy_xattrs(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// Byte-for-byte comparison, reading both files in step so neither has to
+// fit in memory and stopping at the first difference. Returns its offset, or
+// None if the files are identical. When one file is a prefix of the other,
+// the difference is where the shorter one ends. A compressed file is
+// compared by its plain content, and the offset is into that.
+pub fn first_difference(a: &Path, b: &Path) -> io::Result<Option<u64>> {
+    let mut file_a = compress::open(a)?;
+    let mut file_b = compress::open(b)?;
+    let mut buf_a = vec![0u8; COMPARE_CHUNK];
+    let mut buf_b = vec![0u8; COMPARE_CHUNK];
+    let mut offset = 0u64;
+
+    loop {
+        let n_a = read_full(&mut file_a, &mut buf_a)?;
+        let n_b = read_full(&mut file_b, &mut buf_b)?;
+        let common = n_a.min(n_b);
+
+        if let Some(i) = buf_a[..common].iter().zip(&buf_b[..common]).position(|(x, y)| x != y) {
+            return Ok(Some(offset + i as u64));
+        }
+        if n_a != n_b {
+            return Ok(Some(offset + common as u64));
+        }
+        if n_a == 0 {
+            return Ok(None);
+        }
+        offset += n_a as u64;
+    }
+}
+
+const COMPARE_CHUNK: usize = 64 * 1024;
+
+// Fills `buf` unless the file ends first, so both files are always compared
+// over the same span. Returns how much was read.
+fn read_full(file: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
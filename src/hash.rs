@@ -0,0 +1,338 @@
+use crate::compress;
+use crate::external::CommandTemplate;
+use clap::ValueEnum;
+use crc32fast::Hasher as Crc32;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// ----------------------
+// HASHING
+// ----------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum HashAlgo {
+    /// Fast, non-cryptographic. Good enough to spot changes
+    Crc32,
+    /// Cryptographic strength, for verifying against published checksums
+    Sha256,
+    /// Cryptographic strength, and faster than SHA-256 on large files since
+    /// it hashes on all cores
+    Blake3,
+}
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => f.write_str(value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+// A finished hash. Compared for equality and printed as lowercase hex with
+// `{:x}`, whatever algorithm produced it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Checksum(Vec<u8>);
+
+impl Checksum {
+    pub fn matches_hex(&self, hex: &str) -> bool {
+        let hex = hex.trim();
+        let hex = hex.strip_prefix("0x").unwrap_or(hex);
+        format!("{:x}", self).eq_ignore_ascii_case(hex)
+    }
+}
+
+impl fmt::LowerHex for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+// How many hex digits --hash-display short keeps. A full CRC32 fits.
+const SHORT_HEX_LEN: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum HashDisplay {
+    /// The complete digest
+    Full,
+    /// The first few hex digits, enough to tell versions apart
+    Short,
+    /// Leave hashes out of log lines
+    None,
+}
+
+impl HashDisplay {
+    // Formats `hash` for a log line, or None if hashes are hidden. JSON
+    // output always carries the full digest.
+    pub fn format(self, hash: &Checksum) -> Option<String> {
+        let hex = format!("{:x}", hash);
+        match self {
+            HashDisplay::Full => Some(hex),
+            HashDisplay::Short => Some(hex.chars().take(SHORT_HEX_LEN).collect()),
+            HashDisplay::None => None,
+        }
+    }
+}
+
+enum HashState {
+    Crc32(Crc32),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+// BLAKE3 only spreads work across threads once it gets a piece this big;
+// below that the thread pool costs more than it saves.
+const BLAKE3_PARALLEL_MIN: usize = 128 * 1024;
+// Read size for BLAKE3, so that each piece is worth parallelizing.
+pub const BLAKE3_READ: usize = 1024 * 1024;
+// Read size for everything else.
+pub const DEFAULT_READ: usize = 8192;
+
+impl HashState {
+    fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Crc32 => HashState::Crc32(Crc32::new()),
+            HashAlgo::Sha256 => HashState::Sha256(Sha256::new()),
+            HashAlgo::Blake3 => HashState::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            HashState::Crc32(h) => h.update(data),
+            HashState::Sha256(h) => h.update(data),
+            HashState::Blake3(h) if data.len() >= BLAKE3_PARALLEL_MIN => {
+                h.update_rayon(data);
+            }
+            HashState::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    fn finalize(self) -> Checksum {
+        match self {
+            HashState::Crc32(h) => Checksum(h.finalize().to_be_bytes().to_vec()),
+            HashState::Sha256(h) => Checksum(h.finalize().to_vec()),
+            HashState::Blake3(h) => Checksum(h.finalize().as_bytes().to_vec()),
+        }
+    }
+}
+
+// Byte window used for comparison when --compare-range is set. The full file
+// is still what gets written; only the decision to sync looks at this window.
+#[derive(Clone, Copy, Debug)]
+pub struct ByteRange {
+    start: u64,
+    // Exclusive. Negative values are relative to the end of the file.
+    end: Option<i64>,
+}
+
+impl ByteRange {
+    // Resolves the range against a file of `len` bytes, clamped to the file.
+    fn bounds(&self, len: u64) -> (u64, u64) {
+        let end = match self.end {
+            None => len,
+            Some(end) if end < 0 => len.saturating_sub(end.unsigned_abs()),
+            Some(end) => (end as u64).min(len),
+        };
+        (self.start.min(end), end)
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once(':').ok_or("expected START:END, e.g. 0:1024 or 0:-32")?;
+
+        let start = if start.is_empty() {
+            0
+        } else {
+            start.parse().map_err(|_| format!("invalid start offset '{}'", start))?
+        };
+        let end = if end.is_empty() {
+            None
+        } else {
+            Some(end.parse().map_err(|_| format!("invalid end offset '{}'", end))?)
+        };
+
+        if let Some(end) = end
+            && end >= 0
+            && (end as u64) < start
+        {
+            return Err(format!("end offset {} is before start offset {}", end, start));
+        }
+
+        Ok(ByteRange { start, end })
+    }
+}
+
+// Everything that decides how a file's checksum is computed.
+#[derive(Clone, Copy, Debug)]
+pub struct HashOptions<'a> {
+    pub algo: HashAlgo,
+    pub range: Option<ByteRange>,
+    pub mmap: bool,
+    // --filter-command: hash this command's output instead of the file.
+    pub filter: Option<&'a CommandTemplate>,
+}
+
+// With --mmap, files at least this big are hashed through a memory map.
+// Below it, setting up the map costs more than it saves.
+const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+// How much of a mapped file is hashed between length checks.
+const MMAP_CHUNK: usize = 8 * 1024 * 1024;
+
+pub fn compute_hash(path: &Path, options: &HashOptions) -> io::Result<Checksum> {
+    let file = File::open(path)?;
+    if let Some(filter) = options.filter {
+        return hash_filtered(file, path, filter, options.algo);
+    }
+    let read_size = if options.algo == HashAlgo::Blake3 { BLAKE3_READ } else { DEFAULT_READ };
+    if compress::is_compressed(path) {
+        return hash_reader(compress::decoder(file, path), options.algo, read_size);
+    }
+
+    let len = file.metadata()?.len();
+    let (start, end) = match options.range {
+        Some(range) => range.bounds(len),
+        None => (0, u64::MAX),
+    };
+
+    if options.mmap && len >= MMAP_THRESHOLD {
+        return hash_mapped(&file, options.algo, start, end);
+    }
+
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(start))?;
+    hash_reader(reader.take(end - start), options.algo, read_size)
+}
+
+pub fn hash_reader(mut reader: impl Read, algo: HashAlgo, read_size: usize) -> io::Result<Checksum> {
+    let mut hasher = HashState::new(algo);
+    let mut buffer = vec![0; read_size];
+
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 { break; }
+        hasher.update(&buffer[..count]);
+    }
+    Ok(hasher.finalize())
+}
+
+// The file's bytes as stored, even for a compressed file (--decompress-b).
+pub fn raw_hash(path: &Path, algo: HashAlgo) -> io::Result<Checksum> {
+    hash_reader(File::open(path)?, algo, DEFAULT_READ)
+}
+
+// `iyr bench-hash`: how long hashing the whole file takes, reading it
+// `read_size` bytes at a time.
+pub fn time_hash(path: &Path, algo: HashAlgo, read_size: usize) -> io::Result<Duration> {
+    let file = File::open(path)?;
+    let started = Instant::now();
+    hash_reader(file, algo, read_size)?;
+    Ok(started.elapsed())
+}
+
+// Hashes data that's already in memory, the same as `compute_hash` would
+// hash a file holding it (with no range or filter).
+pub fn hash_bytes(data: &[u8], algo: HashAlgo) -> Checksum {
+    let mut hasher = HashState::new(algo);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+// The file is fed to the filter on stdin (and is also available as {file}).
+// Only what the filter prints is hashed, so differences it strips out never
+// count as a change; what gets synced is still the file itself.
+fn hash_filtered(file: File, path: &Path, filter: &CommandTemplate, algo: HashAlgo) -> io::Result<Checksum> {
+    // A filter that can't be started must not look like a missing file, or
+    // the caller would treat it as a deletion.
+    let output = filter
+        .to_command(&[("file", path)])
+        .stdin(file)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| io::Error::other(format!("could not run filter command: {}", e)))?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("filter command exited with {}", output.status)));
+    }
+
+    Ok(hash_bytes(&output.stdout, algo))
+}
+
+// Reading a page of a mapped file that another process has truncated away
+// raises SIGBUS. The file's length is checked again before each chunk, which
+// turns a truncation into an error in all but a very narrow window.
+fn hash_mapped(file: &File, algo: HashAlgo, start: u64, end: u64) -> io::Result<Checksum> {
+    // SAFETY: the map is only ever read, and never past the file's current
+    // length (see above).
+    let map = unsafe { memmap2::Mmap::map(file)? };
+    #[cfg(unix)]
+    let _ = map.advise(memmap2::Advice::Sequential);
+
+    let end = (end as usize).min(map.len());
+    let start = (start as usize).min(end);
+
+    let mut hasher = HashState::new(algo);
+    let mut offset = start;
+    for chunk in map[start..end].chunks(MMAP_CHUNK) {
+        offset += chunk.len();
+        if file.metadata()?.len() < offset as u64 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while it was being hashed"));
+        }
+        hasher.update(chunk);
+    }
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds(range: &str, len: u64) -> (u64, u64) {
+        range.parse::<ByteRange>().unwrap().bounds(len)
+    }
+
+    #[test]
+    fn range_with_both_ends() {
+        assert_eq!(bounds("16:1024", 4096), (16, 1024));
+    }
+
+    #[test]
+    fn open_ends_cover_the_file() {
+        assert_eq!(bounds(":", 4096), (0, 4096));
+        assert_eq!(bounds("100:", 4096), (100, 4096));
+        assert_eq!(bounds(":100", 4096), (0, 100));
+    }
+
+    #[test]
+    fn negative_end_counts_from_the_end() {
+        assert_eq!(bounds("0:-32", 4096), (0, 4064));
+        assert_eq!(bounds("100:-32", 4096), (100, 4064));
+    }
+
+    #[test]
+    fn clamped_to_a_short_file() {
+        assert_eq!(bounds("0:1024", 10), (0, 10));
+        assert_eq!(bounds("20:1024", 10), (10, 10));
+        assert_eq!(bounds("0:-32", 10), (0, 0));
+    }
+
+    #[test]
+    fn rejects_malformed_ranges() {
+        for bad in ["1024", "a:10", "0:b", "-1:10", "100:50"] {
+            assert!(bad.parse::<ByteRange>().is_err(), "{} parsed", bad);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use notify_debouncer_full::DebouncedEvent;
+use std::path::Path;
+
+// ----------------------
+// IGNORED EVENTS
+// ----------------------
+
+// Editor sidecar files that show up next to the watched files: vim swap
+// files and its `4913` write probe, emacs lock and backup files, and
+// generic temp files, plus iyr's own scratch file.
+pub const DEFAULT_PATTERNS: &[&str] = &["*.swp", "*.swx", "*~", ".#*", "#*#", "4913", "*.tmp", "*.iyr-tmp"];
+
+// File-name globs for events that can be dropped as soon as they arrive,
+// before any path is compared or canonicalized.
+pub struct IgnoreSet {
+    set: GlobSet,
+}
+
+impl IgnoreSet {
+    pub fn new(patterns: &[String]) -> Result<Self, globset::Error> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(Glob::new(pattern)?);
+        }
+        Ok(IgnoreSet { set: builder.build()? })
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| self.set.is_match(name))
+    }
+
+    // An event is only dropped if every path in it is ignored, so a rename
+    // from a temp file onto a watched file still gets through.
+    pub fn ignores(&self, event: &DebouncedEvent) -> bool {
+        !event.paths.is_empty() && event.paths.iter().all(|p| self.matches(p))
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+// ----------------------
+// LOG OUTPUT
+// ----------------------
+
+// Every line the tool prints goes through `log_out!` / `log_err!` so it can
+// also be mirrored to the --tee-log file.
+macro_rules! log_out {
+    ($($arg:tt)*) => { $crate::logging::write_line(false, format_args!($($arg)*)) };
+}
+
+macro_rules! log_err {
+    ($($arg:tt)*) => { $crate::logging::write_line(true, format_args!($($arg)*)) };
+}
+
+static TEE: Mutex<Option<RotatingFile>> = Mutex::new(None);
+
+// Pair label put in front of every line so logs from several instances can
+// be told apart once aggregated.
+static PAIR_ID: OnceLock<String> = OnceLock::new();
+
+// In --json mode stdout carries only JSON events, one per line, so that it
+// stays parseable. Human-readable lines are moved to stderr.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+// --summary-json-stdout: stdout is kept for the final report alone, so
+// human-readable lines go to stderr as in --json mode, but no events are
+// emitted.
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+// --json-pretty: events are spread over several indented lines instead.
+static PRETTY: AtomicBool = AtomicBool::new(false);
+
+// Carried by every JSON event as `schema_version`. Bumped whenever a field
+// is removed or changes meaning; new fields and event types don't bump it.
+pub const SCHEMA_VERSION: u64 = 1;
+
+// --relative-display: paths in human-readable lines are shown relative to
+// this directory. JSON events keep them canonical.
+static DISPLAY_BASE: OnceLock<PathBuf> = OnceLock::new();
+
+// Append-only log file that is moved to `<path>.1` once it grows past
+// `max_size` bytes. Only one rotated generation is kept.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: Option<u64>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, file, size, max_size })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, rotated)?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(max) = self.max_size
+            && self.size > 0
+            && self.size + buf.len() as u64 > max
+        {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// Fans a write out to two sinks. The second one is optional so the console
+// path doesn't need a separate code path when no tee file is configured.
+struct Tee<A: Write, B: Write> {
+    first: A,
+    second: Option<B>,
+}
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.first.write_all(buf)?;
+        if let Some(second) = self.second.as_mut() {
+            second.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.first.flush()?;
+        if let Some(second) = self.second.as_mut() {
+            second.flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub fn init_tee(path: PathBuf, max_size_mb: Option<u64>) -> io::Result<()> {
+    let file = RotatingFile::open(path, max_size_mb.map(|mb| mb * 1024 * 1024))?;
+    *TEE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    Ok(())
+}
+
+pub fn set_pair_id(id: String) {
+    let _ = PAIR_ID.set(id);
+}
+
+pub fn set_display_base(base: PathBuf) {
+    let _ = DISPLAY_BASE.set(base);
+}
+
+// How `path` is shown in a log line: relative to the --relative-display base
+// if it's under it, as-is otherwise.
+pub fn shown(path: &Path) -> &Path {
+    match DISPLAY_BASE.get().and_then(|base| path.strip_prefix(base).ok()) {
+        Some(relative) if relative.as_os_str().is_empty() => Path::new("."),
+        Some(relative) => relative,
+        None => path,
+    }
+}
+
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+// Whether stdout is kept for machine-readable output (--json or
+// --summary-json-stdout), which nothing else may write to.
+pub fn stdout_is_machine() -> bool {
+    JSON.load(Ordering::Relaxed) || STDOUT_RESERVED.load(Ordering::Relaxed)
+}
+
+pub fn set_json(enabled: bool, pretty: bool) {
+    JSON.store(enabled, Ordering::Relaxed);
+    PRETTY.store(pretty, Ordering::Relaxed);
+}
+
+// Writes one JSON event to stdout (and the tee file). No-op outside --json.
+pub fn emit_json(mut event: serde_json::Value) {
+    if !JSON.load(Ordering::Relaxed) {
+        return;
+    }
+
+    // Right after `type`, which every event leads with.
+    if let Some(fields) = event.as_object_mut() {
+        fields.shift_insert(fields.len().min(1), "schema_version".to_string(), SCHEMA_VERSION.into());
+        if let Some(id) = PAIR_ID.get() {
+            fields.shift_insert(fields.len().min(2), "pair_id".to_string(), id.as_str().into());
+        }
+    }
+    let line = if PRETTY.load(Ordering::Relaxed) {
+        format!("{:#}\n", event)
+    } else {
+        format!("{}\n", event)
+    };
+    let mut tee_file = TEE.lock().unwrap_or_else(|e| e.into_inner());
+    let _ = Tee { first: io::stdout().lock(), second: tee_file.as_mut() }.write_all(line.as_bytes());
+}
+
+// Failures are events too, so a supervisor can classify them without
+// parsing stderr. `stage` names what was being done: "open", "validation",
+// "read", "compare", "sync", "usage" or "run".
+pub fn emit_error(stage: &str, message: &str, path: Option<&Path>) {
+    emit_json(serde_json::json!({ "type": "error", "stage": stage, "message": message, "path": path }));
+}
+
+pub fn write_line(to_stderr: bool, args: fmt::Arguments) {
+    let to_stderr = to_stderr || stdout_is_machine();
+
+    // Format up front so a rotation can't split a line across two files.
+    let line = match PAIR_ID.get() {
+        Some(id) => format!("[{}] {}\n", id, args),
+        None => format!("{}\n", args),
+    };
+    let mut tee_file = TEE.lock().unwrap_or_else(|e| e.into_inner());
+
+    // A failing log sink must never take the sync down with it.
+    let _ = if to_stderr {
+        Tee { first: io::stderr().lock(), second: tee_file.as_mut() }.write_all(line.as_bytes())
+    } else {
+        Tee { first: io::stdout().lock(), second: tee_file.as_mut() }.write_all(line.as_bytes())
+    };
+}
@@ -1,248 +1,2590 @@
-use clap::Parser;
+use age::secrecy::SecretString;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use regex::Regex;
 use notify_debouncer_full::{
-    new_debouncer,
+    DebounceEventResult, new_debouncer,
     notify::{EventKind, RecursiveMode},
 };
-use std::{fs::{self, File}, path::Path};
-use std::sync::mpsc::channel;
-use std::time::Duration;
-use std::io::{Read, BufReader, self};
-use crc32fast::Hasher;
+use std::{fs, path::Path};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender, channel};
+use std::time::{Duration, Instant, SystemTime};
+use std::io::{self, IsTerminal};
+use std::process::Stdio;
 use std::path::PathBuf;
+use std::sync::Arc;
+
+#[macro_use]
+mod logging;
+mod backup;
+mod batch;
+mod bench;
+mod breaker;
+mod compress;
+mod desktop;
+mod external;
+mod fileops;
+mod hash;
+mod ignore;
+mod preflight;
+mod report;
+mod snapshot;
+mod stats;
+mod validate;
+mod window;
+use backup::BackupTemplate;
+use breaker::CircuitBreaker;
+use external::CommandTemplate;
+use compress::Compression;
+use fileops::{clone_file, write_atomic};
+use ignore::IgnoreSet;
+use hash::{ByteRange, Checksum, HashAlgo, HashDisplay, HashOptions, compute_hash, hash_bytes};
+use logging::shown;
+use stats::Stats;
+use validate::ValidationReport;
+use window::SyncWindow;
 
 // ----------------------
 // CLI ARGS
 // ----------------------
 #[derive(Parser)]
+#[command(version, long_version = long_version())]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    path_a: String,
-    path_b: String,
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[arg(required_unless_present_any = ["print_config", "batch"])]
+    path_a: Option<String>,
+    #[arg(required_unless_present_any = ["expect_hash", "print_config", "batch"])]
+    path_b: Option<String>,
+
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Before a startup conflict overwrites a file, show what's about to
+    /// happen and ask once to go ahead
+    #[arg(long)]
+    confirm_destructive: bool,
+
+    /// Answer yes to --confirm-destructive without asking
+    #[arg(long, requires = "confirm_destructive")]
+    yes: bool,
+
+    /// Print what the startup sync would do (copies, bytes, conflicts and
+    /// their direction) and exit without writing anything. Works with --batch
+    #[arg(long, conflicts_with_all = ["verify", "create_dirs", "diff_command", "encrypt_backups", "expect_hash"])]
+    preflight: bool,
+
+    /// Repair pass: if the files have the same content but different
+    /// permissions, copy A's permissions onto B and exit. Content is never
+    /// written
+    #[arg(long, conflicts_with_all = ["verify", "preflight", "batch", "expect_hash", "create_dirs", "diff_command", "encrypt_backups"])]
+    mirror_permissions_only: bool,
+
+    /// Which file wins when both have different content
+    #[arg(long, value_enum, default_value_t = ConflictStrategy::AWins)]
+    conflict: ConflictStrategy,
+
+    /// Check whether the files are identical and exit (0 = identical,
+    /// 1 = differ). Files are only ever opened for reading
+    #[arg(
+        long,
+        visible_alias = "read-only-check",
+        conflicts_with_all = ["overwrite", "create_dirs", "diff_command", "encrypt_backups"]
+    )]
+    verify: bool,
+
+    /// Check every pair listed in FILE (`-` for stdin), one "A B" per line,
+    /// and report all mismatches. Exits 1 if any pair differs
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["path_a", "path_b", "overwrite", "create_dirs", "diff_command", "expect_hash"]
+    )]
+    batch: Option<PathBuf>,
+
+    /// Encrypt backups with a passphrase (from IYR_BACKUP_PASSPHRASE or a
+    /// prompt). Backups are written as `<name>.age`
+    #[arg(long)]
+    encrypt_backups: bool,
+
+    /// Name backups with this template. Placeholders: {name}, {stem}, {ext},
+    /// {ts} (Unix time), {n} (counter) [default: {stem}_backup.{ext}]
+    #[arg(long, value_name = "TEMPLATE")]
+    backup_template: Option<BackupTemplate>,
+
+    /// Name backups by appending this to the file name, e.g. `.orig` or `~`
+    #[arg(long, value_name = "SUFFIX", conflicts_with = "backup_template", value_parser = BackupTemplate::from_suffix)]
+    backup_suffix: Option<BackupTemplate>,
+
+    /// Don't make a backup whose content matches the file's latest backup
+    #[arg(long, conflicts_with = "encrypt_backups")]
+    dedupe_backups: bool,
+
+    /// Deleting one file deletes the other (default: restore it from the peer)
+    #[arg(long, requires = "force")]
+    mirror_deletes: bool,
+
+    /// With --mirror-deletes or --empty-means delete, move the other file to
+    /// the trash instead of deleting it. Where there's no trash, it's backed
+    /// up, then deleted
+    #[arg(long)]
+    trash: bool,
+
+    /// Confirm destructive options such as --mirror-deletes and
+    /// --empty-means delete
+    #[arg(long)]
+    force: bool,
+
+    /// Hash algorithm used to detect changes
+    #[arg(long, value_enum, default_value_t = HashAlgo::Crc32)]
+    hash_algo: HashAlgo,
+
+    /// Copy extended attributes along with the content (no-op where xattrs
+    /// aren't supported)
+    #[arg(long)]
+    preserve_xattr: bool,
+
+    /// Don't flush each write to stable storage before reporting it. Faster,
+    /// but a sync logged just before a power loss can be lost
+    #[arg(long)]
+    no_fsync: bool,
+
+    /// Read each backup back and check it against the file before anything
+    /// overwrites the file. A backup that doesn't match stops the overwrite
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    verify_backups: bool,
+
+    /// Scan all of each file, not just its start, and refuse it if it
+    /// contains NUL or other control characters (tab, newline and CR are fine)
+    #[arg(long)]
+    strict_text: bool,
+
+    /// Skip a sync that would leave less than this many megabytes free on
+    /// the destination's filesystem. It's tried again on the next change
+    #[arg(long, value_name = "MB")]
+    min_free_space: Option<u64>,
+
+    /// Only sync a change if the new content matches this regex, e.g.
+    /// "(?m)^\[settings\]" for a config that must keep its header
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    sync_if_matches: Option<Regex>,
+
+    /// Don't sync a change if the new content matches this regex, e.g.
+    /// "<<<<<<<" to hold back unresolved merge conflicts
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    sync_unless_matches: Option<Regex>,
+
+    /// Remove a UTF-8 byte order mark from content as it is synced
+    #[arg(long, conflicts_with = "add_bom")]
+    strip_bom: bool,
+
+    /// Make sure content starts with a UTF-8 byte order mark as it is synced
+    #[arg(long)]
+    add_bom: bool,
+
+    /// Hash files of 64 MiB and up through a memory map instead of reads
+    #[arg(long)]
+    mmap: bool,
+
+    /// Compare files by this command's output instead of their raw bytes,
+    /// e.g. a formatter. It gets the file on stdin (or as {file})
+    #[arg(long, value_name = "CMD", conflicts_with = "compare_range")]
+    filter_command: Option<CommandTemplate>,
+
+    /// Decide whether the files are equal with this command instead, e.g.
+    /// "jd -set {a} {b}". Exit code 0 means equal. Only run when hashes differ
+    #[arg(long, value_name = "CMD")]
+    compare_command: Option<CommandTemplate>,
+
+    /// How hashes are shown in log lines
+    #[arg(long, value_enum, default_value_t = HashDisplay::Full)]
+    hash_display: HashDisplay,
+
+    /// Check PATH_A against this hex checksum and exit (0 = match, 1 = mismatch)
+    #[arg(long, value_name = "HEX", conflicts_with = "path_b")]
+    expect_hash: Option<String>,
+
+    /// At startup, take files with the same size and modification time to be
+    /// identical without hashing them, like rsync's quick check
+    #[arg(long, conflicts_with_all = ["verify", "batch", "expect_hash"])]
+    quick_check: bool,
+
+    /// B is stored compressed. It's compared by its decompressed content,
+    /// and compressed again whenever it's written
+    #[arg(long, value_enum, value_name = "FORMAT",
+          conflicts_with_all = ["quick_check", "compare_range", "filter_command", "copy_mode", "batch"])]
+    decompress_b: Option<Compression>,
+
+    /// Only hash bytes START..END when deciding whether files differ.
+    /// A negative END counts back from the end of the file (e.g. 0:-32)
+    #[arg(long, value_name = "START:END")]
+    compare_range: Option<ByteRange>,
+
+    /// How long a file must be quiet before its events are handled
+    #[arg(long, value_name = "MS", default_value_t = 500, value_parser = clap::value_parser!(u64).range(1..))]
+    debounce_ms: u64,
+
+    /// Only sync a change once the file hashes the same twice, MS apart, for
+    /// tools that write a file in several flushes
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    stability_window_ms: Option<u64>,
+
+    /// Only record changes for the first SECS seconds of watching, then sync
+    /// the settled state once, e.g. to sit out an editor's autosaves on startup
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    watch_delay: Option<u64>,
+
+    /// Only apply syncs during this time of day (local time), e.g.
+    /// "09:00-17:00". Changes outside it are synced once it opens
+    #[arg(long, value_name = "HH:MM-HH:MM")]
+    sync_window: Option<SyncWindow>,
+
+    /// Upper bound on how long a change can wait before it's synced, even
+    /// while the file keeps changing. Must be larger than --debounce-ms
+    #[arg(long, value_name = "MS")]
+    debounce_max_wait: Option<u64>,
+
+    /// Log every event the watcher delivers, before any filtering
+    #[arg(long)]
+    debounce_debug: bool,
+
+    /// Pause syncing for a while if events for the pair arrive faster than
+    /// this for several seconds, e.g. when another tool fights over a file
+    #[arg(long, value_name = "N")]
+    max_events_per_sec: Option<u32>,
+
+    /// File-name glob for events to drop straight away, e.g. editor swap
+    /// files. Repeat for more; replaces the defaults
+    #[arg(long = "ignore", value_name = "GLOB", value_parser = parse_glob, default_values = ignore::DEFAULT_PATTERNS)]
+    ignore_patterns: Vec<String>,
+
+    /// What to register with the OS watcher: the files' parent directories,
+    /// or just the two files (fewer wakeups in busy directories)
+    #[arg(long, value_enum, default_value_t = WatchMode::Parent)]
+    watch_mode: WatchMode,
+
+    /// How B is kept in line with A. `hardlink` needs both on one filesystem
+    /// and falls back to copying if an editor breaks the link
+    #[arg(long, value_enum, default_value_t = CopyMode::Copy)]
+    copy_mode: CopyMode,
+
+    /// Unix only: remember each file's inode at startup, and decide what to
+    /// do when the path turns out to hold a different file
+    #[arg(long, value_enum, value_name = "POLICY")]
+    track_inode: Option<InodePolicy>,
+
+    /// Count events that only touched a file (its content didn't change)
+    /// without logging them
+    #[arg(long)]
+    ignore_mtime_only_changes: bool,
+
+    /// Only react to events whose path exactly matches the canonical file path
+    #[arg(long)]
+    strict_paths: bool,
+
+    /// Use a symlinked path as it is instead of resolving it to its target.
+    /// The link's directory is watched, and a sync replaces the link itself
+    #[arg(long)]
+    no_follow_symlinks: bool,
+
+    /// Whether writing to a symlinked path updates the link's target. With
+    /// false, the link is backed up and replaced by a regular file at startup
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    dereference_on_write: bool,
+
+    /// Exit on the first sync error instead of logging it and carrying on
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Create PATH_B, and any missing parent directories, if it doesn't exist
+    #[arg(long)]
+    create_dirs: bool,
+
+    /// On a startup conflict, run this diff/merge tool first, e.g.
+    /// "meld {a} {b}". The files are compared again once it exits
+    #[arg(long, value_name = "CMD")]
+    diff_command: Option<CommandTemplate>,
+
+    /// Resolve every conflict with this command, e.g.
+    /// "merge.sh {a} {b} {base} {out}". What it writes to {out} replaces
+    /// both files, after they're backed up
+    #[arg(long, value_name = "CMD", conflicts_with_all = ["conflict", "read_only_peer"])]
+    reconcile_command: Option<CommandTemplate>,
+
+    /// Don't copy the non-empty file onto an empty one at startup
+    #[arg(long)]
+    no_seed: bool,
+
+    /// What emptying a file while watching means for its peer
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = EmptyMeans::Content, requires_if("delete", "force"))]
+    empty_means: EmptyMeans,
+
+    /// Same as --empty-means ignore
+    #[arg(long, conflicts_with = "empty_means")]
+    no_empty_propagation: bool,
+
+    /// While watching, don't sync a change that leaves a file more than N%
+    /// smaller than its peer, the content last synced. It's logged instead
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..100))]
+    max_shrink_percent: Option<u8>,
+
+    /// Regex for file names that cloud clients use for conflict copies
+    #[arg(long, value_parser = Regex::new, default_value = DEFAULT_CLOUD_CONFLICT_PATTERN)]
+    cloud_conflict_pattern: Regex,
+
+    /// Regex for names of files iyr writes itself, backups and conflict
+    /// copies. Events that only touch those are dropped (default: the names
+    /// the backup template and keep-both give them)
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    backup_ignore_pattern: Option<Regex>,
+
+    /// Label put in front of every log line (default: the file name)
+    #[arg(long, value_name = "STRING")]
+    pair_id: Option<String>,
+
+    /// Show paths in log lines relative to the current directory, or to
+    /// --relative-display=DIR. Paths outside it stay absolute
+    #[arg(long, value_name = "DIR", num_args = 0..=1, require_equals = true)]
+    relative_display: Option<Option<PathBuf>>,
+
+    /// Emit machine-readable events as JSON lines on stdout. Human-readable
+    /// messages move to stderr
+    #[arg(long)]
+    json: bool,
+
+    /// Like --json, but each event is pretty-printed over several lines
+    #[arg(long)]
+    json_pretty: bool,
+
+    /// Also append all log output to this file
+    #[arg(long, value_name = "PATH")]
+    tee_log: Option<PathBuf>,
+
+    /// Rotate the --tee-log file once it exceeds this many megabytes
+    #[arg(long, value_name = "MB", requires = "tee_log")]
+    log_max_size: Option<u64>,
+
+    /// While watching, compare the files byte for byte every SECS seconds
+    /// and report any difference the change detection missed
+    #[arg(long, value_name = "SECS", conflicts_with_all = ["compare_range", "filter_command", "compare_command"], value_parser = clap::value_parser!(u64).range(1..))]
+    verify_after_watch: Option<u64>,
+
+    /// While watching, log a line every SECS seconds saying whether the
+    /// files are in sync, so a quiet watcher can be told from a dead one
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    heartbeat: Option<u64>,
+
+    /// Fix a difference found by --verify-after-watch by copying A over B
+    #[arg(long, requires = "verify_after_watch")]
+    heal: bool,
+
+    /// Show a desktop notification when a conflict overwrites a file
+    #[arg(long)]
+    notify_on_conflict: bool,
+
+    /// Run this once the startup sync is done and watching has begun.
+    /// Gets IYR_EVENT, IYR_PATH_A, IYR_PATH_B; {a} and {b} are substituted
+    #[arg(long, value_name = "CMD")]
+    on_start_command: Option<CommandTemplate>,
+
+    /// Run this once when the watcher shuts down, including on SIGINT or
+    /// SIGTERM. Also gets IYR_SYNCS and IYR_CONFLICTS
+    #[arg(long, value_name = "CMD")]
+    on_stop_command: Option<CommandTemplate>,
+
+    /// Stop syncing, but keep watching and reporting changes, once this
+    /// many conflicts have happened, e.g. when two tools keep fighting
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_conflicts: Option<u64>,
+
+    /// Run this once when --max-conflicts halts syncing
+    #[arg(long, value_name = "CMD", requires = "max_conflicts")]
+    on_halt_command: Option<CommandTemplate>,
+
+    /// Treat B as a read-only mirror of A: edits to B are never synced to A,
+    /// but reported (`alert`) or undone from A (`revert`)
+    #[arg(long, value_enum, value_name = "MODE", conflicts_with = "conflict")]
+    read_only_peer: Option<PeerMode>,
+
+    /// Run this whenever the read-only peer is edited or deleted
+    #[arg(long, value_name = "CMD", requires = "read_only_peer")]
+    on_peer_edit_command: Option<CommandTemplate>,
+
+    /// Write a JSON report of every sync, conflict and error, plus the final
+    /// hashes, to PATH when iyr exits
+    #[arg(long, value_name = "PATH")]
+    report_file: Option<PathBuf>,
+
+    /// Print only the final report, as a single JSON object on stdout, when
+    /// iyr exits. Log lines go to stderr
+    #[arg(long, conflicts_with_all = ["report_file", "json", "json_pretty"])]
+    summary_json_stdout: bool,
+
+    /// Print run statistics when the process receives SIGUSR1 (Unix only)
+    #[arg(long)]
+    summary_on_sigusr1: bool,
+
+    /// Re-hash both files and sync whatever changed when the process
+    /// receives SIGUSR2, as if an event had fired (Unix only)
+    #[arg(long)]
+    reconcile_on_sigusr2: bool,
+
+    /// Print every option's effective value and where it came from, then exit
+    #[arg(long)]
+    print_config: bool,
+}
+
+impl Cli {
+    fn rules(&self) -> preflight::Rules {
+        preflight::Rules {
+            overwrite: self.overwrite,
+            no_seed: self.no_seed,
+            conflict: self.conflict,
+            reconcile: self.reconcile_command.is_some(),
+            read_only_peer: self.read_only_peer.is_some(),
+        }
+    }
+
+    fn follow_symlinks(&self) -> bool {
+        !self.no_follow_symlinks && self.dereference_on_write
+    }
+
+    fn empty_means(&self) -> EmptyMeans {
+        if self.no_empty_propagation { EmptyMeans::Ignore } else { self.empty_means }
+    }
+
+    fn backup_template(&self) -> BackupTemplate {
+        self.backup_template.clone().or_else(|| self.backup_suffix.clone()).unwrap_or_default()
+    }
+
+    // --backup-ignore-pattern, or else the names of the backups iyr makes of
+    // `paths` and of keep-both conflict copies.
+    fn own_files_pattern(&self, paths: &[&Path]) -> Result<Regex, regex::Error> {
+        if let Some(pattern) = &self.backup_ignore_pattern {
+            return Ok(pattern.clone());
+        }
+        let backups = self.backup_template().backups_pattern(paths)?;
+        Regex::new(&format!("{}|{}", backups.as_str(), CONFLICT_COPY_PATTERN))
+    }
+
+    fn hash_options(&self) -> HashOptions<'_> {
+        HashOptions {
+            algo: self.hash_algo,
+            range: self.compare_range,
+            mmap: self.mmap,
+            filter: self.filter_command.as_ref(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ConflictStrategy {
+    /// A overwrites B
+    AWins,
+    /// The larger file overwrites the smaller one (A wins a tie)
+    PreferLarger,
+    /// The smaller file overwrites the larger one (A wins a tie)
+    PreferSmaller,
+    /// Nothing is overwritten: each file's content is copied next to the
+    /// other as a conflict copy, to be merged by hand
+    KeepBoth,
+}
+
+impl ConflictStrategy {
+    // Only asked when there is a winner, i.e. not for keep-both.
+    fn a_wins(self, len_a: u64, len_b: u64) -> bool {
+        match self {
+            ConflictStrategy::AWins | ConflictStrategy::KeepBoth => true,
+            ConflictStrategy::PreferLarger => len_a >= len_b,
+            ConflictStrategy::PreferSmaller => len_a <= len_b,
+        }
+    }
+}
+
+// --conflict keep-both: a free name like `notes (conflicted copy from B).txt`
+// next to `next_to`, for the content that came from the other side. Copies
+// are never compressed, so they're named after a compressed file's plain name.
+fn conflict_copy_path(next_to: &Path, from: &str) -> PathBuf {
+    let name = next_to.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let plain = Path::new(compress::plain_name(next_to, &name));
+    let stem = plain.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let ext = plain.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let mut n = 1;
+    loop {
+        let name = match n {
+            1 => format!("{} (conflicted copy from {}){}", stem, from, ext),
+            _ => format!("{} (conflicted copy from {} {}){}", stem, from, n, ext),
+        };
+        let path = next_to.with_file_name(name);
+        if !path.exists() {
+            return path;
+        }
+        n += 1;
+    }
+}
+
+// Copies B next to A and A next to B, leaving both files as they are.
+// Returns the two copies, the one next to A first.
+fn keep_both(path_a: &Path, path_b: &Path) -> io::Result<(PathBuf, PathBuf)> {
+    let copy_a = conflict_copy_path(path_a, "B");
+    fileops::copy_over(path_b, &copy_a)?;
+    let copy_b = conflict_copy_path(path_b, "A");
+    clone_file(path_a, &copy_b)?;
+    Ok((copy_a, copy_b))
+}
+
+// --reconcile-command: runs the user's resolver on a conflict, then backs up
+// both files and writes its output over them. {base} would be the last
+// content the two had in common, but iyr keeps no record of that, so it's an
+// empty file, which merge tools take as "no common ancestor". If the command
+// fails or leaves {out} unreadable, nothing is backed up or written.
+fn reconcile(cmd: &CommandTemplate, path_a: &Path, path_b: &Path, args: &Cli, passphrase: Option<&SecretString>) -> io::Result<String> {
+    // Private, so nobody else can plant or read the files the command is
+    // handed.
+    let scratch = fileops::private_temp_dir("iyr-reconcile-")?;
+    let (base, out) = (scratch.path().join("base"), scratch.path().join("out"));
+
+    let result = (|| {
+        fs::File::create(&base)?;
+        let status = cmd.run(&[("a", path_a), ("b", path_b), ("base", &base), ("out", &out)])?;
+        if !status.success() {
+            return Err(io::Error::other(format!("reconcile command exited with {}", status)));
+        }
+        let content = read_text(&out).map_err(|e| io::Error::new(e.kind(), format!("reconcile command output: {}", e)))?;
+
+        for path in [path_a, path_b] {
+            let backup = backup::create_backup(path, &path.to_string_lossy(), &args.backup_template(), passphrase, args.dedupe_backups)?;
+            log_out!("   Backup: {:?}", shown(&backup));
+        }
+        // Both are staged before either is replaced, so a failed write
+        // leaves both as they were.
+        let staged_a = fileops::stage(path_a, content.as_bytes())?;
+        let staged_b = fileops::stage(path_b, content.as_bytes())?;
+        staged_a.commit()?;
+        staged_b.commit()?;
+        Ok(content)
+    })();
+
+    let _ = scratch.close();
+    result
+}
+
+impl std::fmt::Display for ConflictStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => f.write_str(value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum PeerMode {
+    /// Warn about the edit and leave B as it is until A next changes
+    Alert,
+    /// Back up the edited B and copy A over it again
+    Revert,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CopyMode {
+    /// Keep two separate files and copy changes between them
+    Copy,
+    /// Make B a hard link to A at startup, so there's nothing to copy
+    Hardlink,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum EmptyMeans {
+    /// Delete the peer too, the way --mirror-deletes would
+    Delete,
+    /// Sync the empty content like any other edit
+    Content,
+    /// Leave the peer's content alone and log the emptying as suspicious
+    Ignore,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum InodePolicy {
+    /// Log the new inode and keep syncing the file at that path
+    Follow,
+    /// Report the tracked file gone and stop
+    Gone,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum WatchMode {
+    /// Watch the containing directories. Sees every kind of save
+    Parent,
+    /// Watch only the two files, re-registering them after each change
+    File,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Put a backup back over a file, decrypting `.age` backups
+    Restore {
+        backup: PathBuf,
+        /// File to overwrite with the backup's content
+        target: PathBuf,
+    },
+    /// Save a copy of both files, with their hashes, sizes and mtimes
+    Snapshot {
+        name: String,
+        path_a: PathBuf,
+        path_b: PathBuf,
+        /// Directory that holds the snapshots
+        #[arg(long, default_value = DEFAULT_SNAPSHOT_DIR)]
+        dir: PathBuf,
+    },
+    /// Put both files of a snapshot back where they were taken from
+    RestoreSnapshot {
+        name: String,
+        /// Directory that holds the snapshots
+        #[arg(long, default_value = DEFAULT_SNAPSHOT_DIR)]
+        dir: PathBuf,
+    },
+    /// Time hashing a file with each --hash-algo and a few read sizes
+    BenchHash { file: PathBuf },
+}
+
+const DEFAULT_SNAPSHOT_DIR: &str = ".iyr-snapshots";
+
+// What notify's recommended watcher uses on this platform.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const WATCHER_BACKEND: &str = "inotify";
+#[cfg(target_os = "macos")]
+const WATCHER_BACKEND: &str = "FSEvents";
+#[cfg(any(target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly", target_os = "ios"))]
+const WATCHER_BACKEND: &str = "kqueue";
+#[cfg(windows)]
+const WATCHER_BACKEND: &str = "ReadDirectoryChangesW";
+#[cfg(not(any(
+    target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd",
+    target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly", target_os = "ios", windows
+)))]
+const WATCHER_BACKEND: &str = "polling";
+
+// `--version`: enough about the build to go in a bug report. The target,
+// profile and commit come from build.rs.
+fn long_version() -> String {
+    let algos: Vec<String> = HashAlgo::value_variants().iter().map(|algo| algo.to_string()).collect();
+    let commit = match env!("IYR_COMMIT") {
+        "" => "unknown",
+        commit => commit,
+    };
+    format!(
+        "{}\ncommit:  {}\ntarget:  {} ({})\nwatcher: {}\nhashes:  {}",
+        env!("CARGO_PKG_VERSION"), commit, env!("IYR_TARGET"), env!("IYR_PROFILE"), WATCHER_BACKEND, algos.join(", ")
+    )
+}
+
+// ----------------------
+// EXIT CODES
+// ----------------------
+// A stable contract for scripts. Documented in the README; don't renumber.
+const EXIT_DIFFER: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_VALIDATION: i32 = 3;
+const EXIT_IO: i32 = 4;
+
+// Dropbox writes `notes (conflicted copy).txt`, OneDrive and others use
+// variations on "conflict" in the copy's name.
+const DEFAULT_CLOUD_CONFLICT_PATTERN: &str = r"(?i)conflict";
+
+// The names `conflict_copy_path` gives keep-both copies.
+const CONFLICT_COPY_PATTERN: &str = r"\(conflicted copy from [AB]( \d+)?\)";
+
+fn parse_glob(s: &str) -> Result<String, globset::Error> {
+    globset::Glob::new(s).map(|_| s.to_string())
+}
+
+// ----------------------
+// HELPER FUNCTIONS
+// ----------------------
+
+// Reads the whole file as UTF-8. The text check only looks at the first
+// 1024 bytes, so a file can pass it and still fail here. Never fall back to
+// an empty string: writing that to the peer would wipe its content.
+fn read_text(path: &Path) -> io::Result<String> {
+    text_from_bytes(compress::read(path)?)
+}
+
+fn text_from_bytes(bytes: Vec<u8>) -> io::Result<String> {
+    let text = String::from_utf8(bytes).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "file contains non-UTF-8 data past the text check",
+        )
+    })?;
+    if validate::strict_text()
+        && let Some((offset, ch)) = validate::find_control_char(&text)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("file contains control character {:?} at byte {} (--strict-text)", ch, offset),
+        ));
+    }
+    Ok(text)
+}
+
+// The size of a file at startup. A file that another program is replacing
+// by rename can be missing for a moment, so a missing file is looked for a
+// few more times before giving up.
+fn startup_len(path: &Path, name: &str) -> u64 {
+    let mut attempts = 0;
+    loop {
+        match compress::len(path) {
+            Ok(len) => return len,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && attempts < 5 => {
+                attempts += 1;
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => {
+                log_err!("❌ Error: File {} ({:?}) went away during startup: {}", name, shown(path), e);
+                log_err!("   Another program may be rewriting it. No files were changed.");
+                logging::emit_error("open", &e.to_string(), Some(path));
+                exit(EXIT_IO);
+            }
+        }
+    }
+}
+
+fn read_text_or_exit(path: &Path) -> String {
+    match read_text(path) {
+        Ok(content) => content,
+        Err(e) => {
+            log_err!("❌ Error: Could not read {:?}: {}", shown(path), e);
+            log_err!("   Aborting sync, no files were changed.");
+            logging::emit_error("read", &e.to_string(), Some(path));
+            if e.kind() == io::ErrorKind::InvalidData {
+                exit(EXIT_VALIDATION);
+            }
+            exit(EXIT_IO);
+        }
+    }
+}
+
+// What a watched file looks like once a debounced batch has settled.
+enum FileState {
+    Missing,
+    Present(Checksum),
+}
+
+fn final_state(path: &Path, options: &HashOptions) -> io::Result<FileState> {
+    match compute_hash(path, options) {
+        Ok(hash) => Ok(FileState::Present(hash)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(FileState::Missing),
+        Err(e) => Err(e),
+    }
+}
+
+// A watched file was deleted. By default the peer is treated as the source of
+// truth and the file is put back; with --mirror-deletes the peer is removed too.
+fn handle_removal(removed: &Path, peer: &Path, names: (&str, &str), args: &Cli, passphrase: Option<&SecretString>) -> io::Result<()> {
+    let (name, peer_name) = names;
+
+    if !peer.exists() {
+        log_out!("🗑️ File {} was deleted and {} is already gone. Nothing to do.", name, peer_name);
+        return Ok(());
+    }
+
+    if args.mirror_deletes && args.trash {
+        log_out!("🗑️ File {} was deleted. Moving {} to the trash (--trash)...", name, peer_name);
+        trash_or_delete(peer, peer_name, args, passphrase)
+    } else if args.mirror_deletes {
+        log_out!("🗑️ File {} was deleted. Deleting {} (--mirror-deletes)...", name, peer_name);
+        fs::remove_file(peer)
+    } else {
+        log_out!("🗑️ File {} was deleted. Restoring it from {}...", name, peer_name);
+        let content = read_text(peer)?;
+        write_atomic(removed, content.as_bytes()).map(|_| ())
+    }
+}
+
+fn delete_emptied_peer(name: &str, peer: &Side, args: &Cli, passphrase: Option<&SecretString>) -> io::Result<()> {
+    if args.trash {
+        log_out!("🗑️ File {} was emptied. Moving {} to the trash (--empty-means delete)...", name, peer.name);
+        return trash_or_delete(&peer.path, peer.name, args, passphrase);
+    }
+    log_out!("🗑️ File {} was emptied. Deleting {} (--empty-means delete)...", name, peer.name);
+    fs::remove_file(&peer.path)
+}
+
+// --trash: moves `path` to the trash, or where there's no trash to move it
+// to, backs it up and deletes it.
+fn trash_or_delete(path: &Path, name: &str, args: &Cli, passphrase: Option<&SecretString>) -> io::Result<()> {
+    match trash::delete(path) {
+        Ok(()) => return Ok(()),
+        Err(e) => log_err!("⚠️ Warning: Could not move {} to the trash ({}). Backing it up, then deleting it.", name, e),
+    }
+    let backup = backup::create_backup(path, &path.to_string_lossy(), &args.backup_template(), passphrase, args.dedupe_backups)?;
+    log_out!("   Backup: {:?}", shown(&backup));
+    fs::remove_file(path)
+}
+
+// One file of the pair as the event loop tracks it.
+struct Side {
+    name: &'static str,
+    path: PathBuf,
+    // Hash of what was last read from or written to the file.
+    hash: Checksum,
+    // Size of the file when `hash` was taken.
+    len: u64,
+    // Touched but not handled yet, because syncing is paused or the file
+    // is still settling.
+    pending: bool,
+    // When the file was first seen empty after it last had content.
+    empty_since: Option<Instant>,
+    // --track-inode: the inode the path is expected to point at.
+    ino: Option<u64>,
+    // Deleted by iyr (--empty-means delete), so its removal isn't undone.
+    deleted: bool,
+    // Modification time when the file was last looked at.
+    mtime: Option<SystemTime>,
+    // --stability-window-ms: the new hash seen on the last look and when it
+    // was first seen. It has to hold for the window before it's synced.
+    unsettled: Option<(Checksum, Instant)>,
+}
+
+impl Side {
+    fn new(name: &'static str, path: PathBuf, hash: Checksum) -> Self {
+        let len = compress::len(&path).unwrap_or(0);
+        let ino = fileops::inode(&path);
+        let mtime = fileops::modified(&path);
+        Side { name, path, hash, len, pending: false, empty_since: None, ino, deleted: false, mtime, unsettled: None }
+    }
+
+    // Hashes the file as it is now. A size change means the file is about to
+    // be copied, so it's read once, for both the hash and the copy, rather
+    // than hashed and then read again. Only possible when the hash covers the
+    // file's raw bytes.
+    fn current_state(&self, options: &HashOptions) -> (io::Result<FileState>, Option<Vec<u8>>) {
+        let resized = compress::len(&self.path).is_ok_and(|len| len != self.len);
+        if !resized || options.range.is_some() || options.filter.is_some() {
+            return (final_state(&self.path, options), None);
+        }
+
+        match compress::read(&self.path) {
+            Ok(bytes) => (Ok(FileState::Present(hash_bytes(&bytes, options.algo))), Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (Ok(FileState::Missing), None),
+            Err(e) => (Err(e), None),
+        }
+    }
+
+    // Called after iyr itself may have replaced the file.
+    fn remember_inode(&mut self) {
+        if let Some(ino) = fileops::take_written_inode(&self.path) {
+            self.ino = Some(ino);
+        }
+    }
+
+    // Editors that save by truncating and then rewriting leave the file empty
+    // for a moment, and a debounced batch can end right in between. An empty
+    // file is only believed once it has stayed empty for `settle`; until then
+    // this returns when to look again.
+    fn settling_until(&mut self, settle: Duration, now: Instant) -> Option<Instant> {
+        let empty = compress::len(&self.path).is_ok_and(|len| len == 0);
+        if !empty {
+            self.empty_since = None;
+            return None;
+        }
+
+        let until = *self.empty_since.get_or_insert(now) + settle;
+        if now < until {
+            return Some(until);
+        }
+        self.empty_since = None;
+        None
+    }
+}
+
+// --read-only-peer: B changed on its own. Nothing is synced to A; the edit
+// is reported, and with `revert` undone from A. Returns when to look again
+// if B is still settling, like `handle_change`.
+fn guard_peer(peer: &mut Side, source: &Side, mode: PeerMode, args: &Cli, passphrase: Option<&SecretString>, stats: &Stats) -> Option<Instant> {
+    peer.pending = false;
+    let deleted = match final_state(&peer.path, &args.hash_options()) {
+        // iyr's own write, or a touch.
+        Ok(FileState::Present(hash)) if hash == peer.hash => return None,
+        Ok(FileState::Present(hash)) => {
+            if let Some(until) = peer.settling_until(Duration::from_millis(args.debounce_ms), Instant::now()) {
+                peer.pending = true;
+                return Some(until);
+            }
+            peer.hash = hash;
+            false
+        }
+        Ok(FileState::Missing) => true,
+        Err(e) => {
+            sync_error(args.fail_fast, format_args!("Error reading {}: {}", peer.name, e));
+            return None;
+        }
+    };
+
+    let what = if deleted { "deleted" } else { "edited" };
+    match fs::metadata(&peer.path).and_then(|m| m.modified()) {
+        Ok(mtime) => log_err!(
+            "🚨 {} was {} (now {} bytes, modified {}), but it's a read-only peer.",
+            peer.name, what, compress::len(&peer.path).unwrap_or(0),
+            chrono::DateTime::<chrono::Local>::from(mtime).format("%Y-%m-%d %H:%M:%S")
+        ),
+        Err(_) => log_err!("🚨 {} was {}, but it's a read-only peer.", peer.name, what),
+    }
+    logging::emit_json(serde_json::json!({
+        "type": "peer_edited",
+        "mode": if mode == PeerMode::Alert { "alert" } else { "revert" },
+        "path": peer.path,
+        "deleted": deleted,
+    }));
+    if args.notify_on_conflict {
+        desktop::notify_peer_edited(&peer.path, what, mode == PeerMode::Revert);
+    }
+    if let Some(hook) = &args.on_peer_edit_command {
+        run_hook(hook, "peer-edit", &source.path, &peer.path, stats);
+    }
+
+    if mode == PeerMode::Alert {
+        log_err!("   Leaving it as it is until {} changes (--read-only-peer alert).", source.name);
+        return None;
+    }
+
+    log_out!("🛡️ Reverting {} from {} (--read-only-peer revert)...", peer.name, source.name);
+    if !deleted {
+        match backup::create_backup(&peer.path, &peer.path.to_string_lossy(), &args.backup_template(), passphrase, args.dedupe_backups) {
+            Ok(backup) => log_out!("   Backup: {:?}", shown(&backup)),
+            Err(e) => {
+                sync_error(args.fail_fast, format_args!("Error backing up {}, not reverting it: {}", peer.name, e));
+                return None;
+            }
+        }
+    }
+    match fileops::copy_over(&source.path, &peer.path) {
+        Ok(bytes) => {
+            stats.record_sync(source.name, peer.name, bytes);
+            preserve_xattrs(args, &source.path, &peer.path);
+            peer.hash = source.hash.clone();
+            peer.len = bytes;
+        }
+        Err(e) => sync_error(args.fail_fast, format_args!("Error reverting {}: {}", peer.name, e)),
+    }
+    None
+}
+
+// --heartbeat: a liveness line. It reports the hashes iyr last saw, so it
+// costs nothing, but a change still waiting to be synced shows as pending.
+fn heartbeat(a: &Side, b: &Side, args: &Cli, halted: bool) {
+    let in_sync = a.hash == b.hash && !a.pending && !b.pending;
+    let status = match (halted, in_sync, a.pending || b.pending) {
+        (true, _, _) => "syncing halted",
+        (false, true, _) => "in sync",
+        (false, false, true) => "changes pending",
+        (false, false, false) => "out of sync",
+    };
+    // Not hashed yet under --quick-check.
+    let hashed = a.hash != Checksum::default();
+    match (args.hash_display.format(&a.hash), args.hash_display.format(&b.hash)) {
+        (Some(hash_a), Some(hash_b)) if hashed => {
+            log_out!("💓 Still watching, {} (A: {}, B: {})", status, hash_a, hash_b)
+        }
+        _ => log_out!("💓 Still watching, {}", status),
+    }
+    logging::emit_json(serde_json::json!({
+        "type": "heartbeat",
+        "status": status,
+        "in_sync": in_sync,
+        "hash_a": hashed.then(|| format!("{:x}", a.hash)),
+        "hash_b": hashed.then(|| format!("{:x}", b.hash)),
+    }));
+}
+
+// --track-inode: checks that a touched path still holds the file that was
+// tracked. A missing file is left to the usual delete handling.
+fn check_inode(side: &mut Side, policy: InodePolicy) {
+    let Some(current) = fileops::inode(&side.path) else { return };
+    let Some(tracked) = side.ino.filter(|&ino| ino != current) else { return };
+    match policy {
+        InodePolicy::Follow => {
+            log_out!("🔁 {} was recreated (inode {} -> {}). Following the new file.", side.name, tracked, current);
+            side.ino = Some(current);
+        }
+        InodePolicy::Gone => {
+            let message = format!(
+                "The tracked file {} (inode {}) is gone; {:?} is now a different file (inode {}).",
+                side.name, tracked, shown(&side.path), current
+            );
+            log_err!("❌ {} Stopping (--track-inode gone).", message);
+            logging::emit_error("sync", &message, Some(&side.path));
+            report::record_error(&message);
+            exit(EXIT_IO);
+        }
+    }
+}
+
+// --verify-after-watch: a full byte-for-byte comparison, to catch drift that
+// hash-based change detection missed (e.g. a write the watcher never saw).
+// With --heal, A is copied over B, the same choice a startup conflict makes.
+fn deep_check(a: &mut Side, b: &mut Side, args: &Cli, stats: &Stats) {
+    // An edit can still be sitting in the debouncer, not yet seen by us. The
+    // check is skipped this round rather than "healing" that edit away.
+    let in_flight = Duration::from_millis(args.debounce_ms) * 2;
+    if modified_within(&a.path, in_flight) || modified_within(&b.path, in_flight) {
+        return;
+    }
+
+    let offset = match fileops::first_difference(&a.path, &b.path) {
+        Ok(Some(offset)) => offset,
+        Ok(None) => return,
+        Err(e) => {
+            sync_error(args.fail_fast, format_args!("Error comparing A and B: {}", e));
+            return;
+        }
+    };
+
+    log_err!("⚠️ Files have drifted apart without a change being seen (first difference at byte {}).", offset);
+    let mut healed = false;
+    if args.heal {
+        log_out!("   Healing: syncing A -> B (--heal)");
+        match read_text(&a.path).and_then(|content| write_atomic(&b.path, content.as_bytes())) {
+            Ok(bytes) => {
+                stats.record_sync("A", "B", bytes);
+                preserve_xattrs(args, &a.path, &b.path);
+                match compute_hash(&a.path, &args.hash_options()) {
+                    Ok(hash) => {
+                        a.hash = hash.clone();
+                        b.hash = hash;
+                        a.len = bytes;
+                        b.len = bytes;
+                    }
+                    Err(e) => sync_error(args.fail_fast, format_args!("Error reading A: {}", e)),
+                }
+                healed = true;
+            }
+            Err(e) => sync_error(args.fail_fast, format_args!("❌ Healing A -> B failed: {}", e)),
+        }
+    }
+    logging::emit_json(serde_json::json!({ "type": "divergence", "offset": offset, "healed": healed }));
+}
+
+// --sync-if-matches / --sync-unless-matches: whether a change is fit to sync.
+fn passes_predicates(args: &Cli, content: &str) -> bool {
+    args.sync_if_matches.as_ref().is_none_or(|re| re.is_match(content))
+        && !args.sync_unless_matches.as_ref().is_some_and(|re| re.is_match(content))
+}
+
+const BOM: char = '\u{feff}';
+
+// --strip-bom / --add-bom: the content to write, and whether that differs
+// from what was read. A BOM is otherwise copied as-is, like any other bytes.
+fn normalize_bom(args: &Cli, content: String) -> (String, bool) {
+    if args.strip_bom && let Some(rest) = content.strip_prefix(BOM) {
+        return (rest.to_string(), true);
+    }
+    if args.add_bom && !content.starts_with(BOM) {
+        return (format!("{}{}", BOM, content), true);
+    }
+    (content, false)
+}
+
+// Writes the synced `content` to `dst`. After a sync changed the BOM, the
+// same content goes back to the source so both files agree. Otherwise the
+// two would differ by the BOM forever, and the write to `dst` would be seen
+// as a change and synced back anyway. Both are staged before either is
+// replaced, so a failed write leaves both as they were.
+fn write_synced(src: &mut Side, dst: &mut Side, new_hash: Checksum, content: &str, normalized: bool, args: &Cli) -> io::Result<()> {
+    let staged_dst = fileops::stage(&dst.path, content.as_bytes())?;
+    let staged_src = if normalized { Some(fileops::stage(&src.path, content.as_bytes())?) } else { None };
+    staged_dst.commit()?;
+    preserve_xattrs(args, &src.path, &dst.path);
+    let Some(staged_src) = staged_src else {
+        dst.hash = new_hash;
+        dst.len = content.len() as u64;
+        return Ok(());
+    };
+
+    staged_src.commit()?;
+    log_out!("   Normalized the BOM in {} too", src.name);
+    preserve_xattrs(args, &dst.path, &src.path);
+    (src.len, dst.len) = (content.len() as u64, content.len() as u64);
+    match compute_hash(&dst.path, &args.hash_options()) {
+        Ok(hash) => {
+            src.hash = hash.clone();
+            dst.hash = hash;
+        }
+        // Both files hold the same content, so the next event settles it.
+        Err(e) => log_err!("⚠️ Warning: Could not hash {}: {}", dst.name, e),
+    }
+    Ok(())
+}
+
+// The startup sync copies bytes, so --strip-bom / --add-bom is applied
+// afterwards, to both files as a sync while watching would. Returns the new
+// hash of both if anything changed.
+fn normalize_bom_at_startup(src: &Path, dst: &Path, args: &Cli) -> io::Result<Option<Checksum>> {
+    if !args.strip_bom && !args.add_bom {
+        return Ok(None);
+    }
+    let (content, normalized) = normalize_bom(args, read_text(dst)?);
+    if !normalized {
+        return Ok(None);
+    }
+    // Both are staged before either is replaced, so a failed write leaves
+    // neither normalized.
+    let staged_dst = fileops::stage(dst, content.as_bytes())?;
+    let staged_src = fileops::stage(src, content.as_bytes())?;
+    staged_dst.commit()?;
+    preserve_xattrs(args, src, dst);
+    staged_src.commit()?;
+    preserve_xattrs(args, dst, src);
+    log_out!("   Normalized the BOM in both files");
+    compute_hash(dst, &args.hash_options()).map(Some)
+}
+
+// --preserve-xattr: copies the source's extended attributes onto a file that
+// was just written. Writes replace the destination outright, so without this
+// it ends up with none at all. A failure is only a warning: the content,
+// which is what matters most, is already in place.
+fn preserve_xattrs(args: &Cli, src: &Path, dst: &Path) {
+    if args.preserve_xattr
+        && let Err(e) = fileops::copy_xattrs(src, dst)
+    {
+        log_err!("⚠️ Warning: Could not copy extended attributes to {:?}: {}", shown(dst), e);
+    }
+}
+
+// --quick-check: rsync's test for "probably unchanged". Anything that
+// can't be read counts as a difference.
+fn same_size_and_mtime(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.len() == b.len() && a.modified().is_ok_and(|t| b.modified().is_ok_and(|u| t == u)),
+        _ => false,
+    }
+}
+
+fn modified_within(path: &Path, window: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .is_ok_and(|t| t.elapsed().is_ok_and(|age| age < window))
+}
+
+// Both files changed within one batch, typically two files in the same
+// directory saved together. The --conflict strategy picks a winner, as in a
+// startup conflict, but the loser's new content is backed up first instead
+// of being silently overwritten. If that backup fails, neither side syncs.
+// With keep-both neither side syncs anyway; returns the conflict copies.
+fn resolve_concurrent_edit(a: &mut Side, b: &mut Side, args: &Cli, passphrase: Option<&SecretString>, stats: &Stats) -> Vec<PathBuf> {
+    let changed = |side: &Side| match compute_hash(&side.path, &args.hash_options()) {
+        Ok(hash) if hash != side.hash => Some(hash),
+        _ => None,
+    };
+    let size = |side: &Side| compress::len(&side.path).unwrap_or(0);
+
+    // Only a real conflict if both sides have different new content.
+    let (Some(hash_a), Some(hash_b)) = (changed(a), changed(b)) else {
+        return Vec::new();
+    };
+    let (len_a, len_b) = (size(a), size(b));
+    if hash_a == hash_b || len_a == 0 || len_b == 0 || compare_says_equal(args, &a.path, &b.path) {
+        return Vec::new();
+    }
+
+    if let Some(cmd) = &args.reconcile_command {
+        log_out!("⚠️ Conflict! A and B changed at the same time. Running --reconcile-command...");
+        stats.record_conflict();
+        a.pending = false;
+        b.pending = false;
+        match reconcile(cmd, &a.path, &b.path, args, passphrase) {
+            Ok(content) => {
+                log_out!("   Reconciled. Wrote {} bytes to both files.", content.len());
+                stats.record_sync("reconcile", "A", content.len() as u64);
+                stats.record_sync("reconcile", "B", content.len() as u64);
+                let hash = compute_hash(&a.path, &args.hash_options()).unwrap_or_default();
+                (a.hash, a.len) = (hash.clone(), content.len() as u64);
+                (b.hash, b.len) = (hash, content.len() as u64);
+            }
+            Err(e) => sync_error(args.fail_fast, format_args!("Error reconciling, leaving both files as they are: {}", e)),
+        }
+        return Vec::new();
+    }
+
+    if args.conflict == ConflictStrategy::KeepBoth {
+        log_out!("⚠️ Conflict! A and B changed at the same time. Keeping both (keep-both)...");
+        stats.record_conflict();
+        a.pending = false;
+        b.pending = false;
+        return match keep_both(&a.path, &b.path) {
+            Ok((copy_a, copy_b)) => {
+                log_out!("   Conflict copies: {:?}, {:?}", shown(&copy_a), shown(&copy_b));
+                if args.notify_on_conflict {
+                    desktop::notify_kept_both(&copy_a, &copy_b);
+                }
+                // Both stay as they are, so the next change to either syncs.
+                (a.hash, a.len) = (hash_a, len_a);
+                (b.hash, b.len) = (hash_b, len_b);
+                vec![copy_a, copy_b]
+            }
+            Err(e) => {
+                sync_error(args.fail_fast, format_args!("Error writing conflict copies, leaving both files as they are: {}", e));
+                Vec::new()
+            }
+        };
+    }
+
+    let (winner, loser) = if args.conflict.a_wins(len_a, len_b) { (a, b) } else { (b, a) };
+    log_out!(
+        "⚠️ Conflict! A and B changed at the same time. Backing up {}, {} wins ({})...",
+        loser.name, winner.name, args.conflict
+    );
+    stats.record_conflict();
+    match backup::create_backup(&loser.path, &loser.path.to_string_lossy(), &args.backup_template(), passphrase, args.dedupe_backups) {
+        Ok(backup) => {
+            log_out!("   Backup: {:?}", shown(&backup));
+            if args.notify_on_conflict {
+                desktop::notify_conflict(&winner.path, &loser.path, &backup);
+            }
+            loser.pending = false;
+        }
+        Err(e) => {
+            sync_error(args.fail_fast, format_args!("Error backing up {}, leaving both files as they are: {}", loser.name, e));
+            winner.pending = false;
+            loser.pending = false;
+        }
+    }
+    Vec::new()
+}
+
+// --min-free-space: whether writing `len` bytes to `dst` would leave less
+// than the minimum free on its filesystem. The temp file needs room for all
+// of it before the old file is replaced. Returns the space available.
+fn short_on_space(args: &Cli, dst: &Path, len: u64) -> Option<u64> {
+    let min = args.min_free_space?.saturating_mul(1024 * 1024);
+    let dir = dst.parent()?;
+    match fs2::available_space(dir) {
+        Ok(free) if free < min.saturating_add(len) => Some(free),
+        Ok(_) => None,
+        Err(e) => {
+            log_err!("⚠️ Warning: Could not check free space for {:?}: {}", shown(dir), e);
+            None
+        }
+    }
+}
+
+// --compare-command: asks the user's comparator whether two files whose
+// hashes differ are equal anyway. Its output is discarded; only the exit
+// code counts. If it can't be run, the files count as different, so the
+// usual rules apply. Always false without the option.
+fn compare_says_equal(args: &Cli, a: &Path, b: &Path) -> bool {
+    let Some(compare) = &args.compare_command else {
+        return false;
+    };
+
+    let mut command = compare.to_command(&[("a", a), ("b", b)]);
+    match command.stdout(Stdio::null()).status() {
+        Ok(status) => status.success(),
+        Err(e) => {
+            log_err!("⚠️ Warning: Could not run --compare-command: {}", e);
+            false
+        }
+    }
+}
+
+// --max-conflicts: from now on changes are only reported. Everything that
+// could alert someone is used, since nothing will fix itself.
+fn halt_syncing(a: &Side, b: &Side, args: &Cli, stats: &Stats) {
+    let conflicts = stats.conflicts();
+    log_err!("🛑 {} conflicts so far (--max-conflicts). Syncing is halted; still watching.", conflicts);
+    log_err!("   Two programs may be writing these files. Restart iyr once that's fixed.");
+    logging::emit_json(serde_json::json!({ "type": "halted", "conflicts": conflicts }));
+    if args.notify_on_conflict {
+        desktop::notify_halted(&a.path, &b.path, conflicts);
+    }
+    if let Some(hook) = &args.on_halt_command {
+        run_hook(hook, "halt", &a.path, &b.path, stats);
+    }
+}
+
+// After a halt, a change is still noticed and logged, but nothing is synced.
+// The hash is kept current so each change is only reported once.
+fn report_while_halted(side: &mut Side, args: &Cli) {
+    if !std::mem::take(&mut side.pending) {
+        return;
+    }
+    match final_state(&side.path, &args.hash_options()) {
+        Ok(FileState::Present(hash)) if hash != side.hash => {
+            log_out!("⏸️ File {} changed. Not syncing it (halted by --max-conflicts).", side.name);
+            side.hash = hash;
+        }
+        Ok(FileState::Missing) => log_out!("⏸️ File {} was deleted. Not syncing it (halted by --max-conflicts).", side.name),
+        Ok(FileState::Present(_)) => {}
+        Err(e) => log_err!("Error reading {}: {}", side.name, e),
+    }
+}
+
+// --copy-mode hardlink: replaces B with a hard link to A once they hold the
+// same content, backing B up first. Anything that gets in the way leaves
+// the pair as two files that are synced by copying, as usual.
+fn link_pair(path_a: &Path, path_b: &Path, input_b: &str, identical: bool, args: &Cli, passphrase: Option<&SecretString>) {
+    if fileops::same_file(path_a, path_b) {
+        log_out!("🔗 B is already a hard link to A.");
+        return;
+    }
+    if !identical {
+        log_err!("⚠️ Warning: A and B still differ, so B isn't linked to A. Syncing by copying instead.");
+        return;
+    }
+    if !cfg!(unix) {
+        log_err!("⚠️ Warning: --copy-mode hardlink isn't supported on this platform. Syncing by copying instead.");
+        return;
+    }
+
+    let linked = backup::create_backup(path_b, input_b, &args.backup_template(), passphrase, args.dedupe_backups)
+        .and_then(|backup| fileops::hard_link_over(path_a, path_b).map(|_| backup));
+    match linked {
+        Ok(backup) => {
+            log_out!("🔗 B is now a hard link to A. Edits made in place show up in both.");
+            log_out!("   Backup: {:?}", shown(&backup));
+        }
+        // EXDEV across filesystems is the usual reason.
+        Err(e) => log_err!("⚠️ Warning: Could not link B to A ({}). Syncing by copying instead.", e),
+    }
+}
+
+// Brings `dst` in line with a change to `src`. Returns when `src` needs to be
+// looked at again if it isn't settled yet.
+fn handle_change(src: &mut Side, dst: &mut Side, args: &Cli, passphrase: Option<&SecretString>, stats: &Stats) -> Option<Instant> {
+    src.pending = false;
+
+    let (state, content) = src.current_state(&args.hash_options());
+    let mtime = fileops::modified(&src.path);
+    let retouched = mtime != std::mem::replace(&mut src.mtime, mtime);
+    let unsettled = src.unsettled.take();
+    // Once the file is back, even through a sync, its removal counts again.
+    src.deleted &= matches!(state, Ok(FileState::Missing));
+    match state {
+        Ok(FileState::Missing) if src.deleted => {}
+        Ok(FileState::Missing) => {
+            src.empty_since = None;
+            match handle_removal(&src.path, &dst.path, (src.name, dst.name), args, passphrase) {
+                Ok(()) => {
+                    if src.path.exists() {
+                        preserve_xattrs(args, &dst.path, &src.path);
+                    }
+                    src.hash = dst.hash.clone();
+                    src.len = dst.len;
+                }
+                Err(e) => sync_error(args.fail_fast, format_args!("Error handling deletion of {}: {}", src.name, e)),
+            }
+        }
+        Ok(FileState::Present(new_hash)) if new_hash != src.hash || !dst.path.exists() => {
+            let settle = Duration::from_millis(args.debounce_ms);
+            if let Some(until) = src.settling_until(settle, Instant::now()) {
+                src.pending = true;
+                return Some(until);
+            }
+
+            // Still being written if the content moved within the window.
+            if let Some(window) = args.stability_window_ms {
+                let since = match unsettled {
+                    Some((hash, since)) if hash == new_hash => since,
+                    _ => Instant::now(),
+                };
+                let stable_at = since + Duration::from_millis(window);
+                if Instant::now() < stable_at {
+                    src.unsettled = Some((new_hash, since));
+                    src.pending = true;
+                    return Some(stable_at);
+                }
+            }
 
-    #[arg(long)]
-    overwrite: bool,
+            // Not hashed at startup (--quick-check). Hashing the other file
+            // once now tells a touch apart from an edit.
+            if src.hash == Checksum::default()
+                && let Ok(FileState::Present(peer)) = final_state(&dst.path, &args.hash_options())
+                && peer == new_hash
+            {
+                src.hash = new_hash;
+                dst.hash = peer;
+                src.len = compress::len(&src.path).unwrap_or(src.len);
+                return None;
+            }
+
+            // `src.len` is still the size from before this change.
+            let emptied = src.len > 0 && compress::len(&src.path).is_ok_and(|len| len == 0);
+            if emptied && args.empty_means() == EmptyMeans::Ignore && dst.len > 0 {
+                log_err!(
+                    "⚠️ Warning: File {} was emptied. Not propagating that to {} (--empty-means ignore).",
+                    src.name, dst.name
+                );
+                src.hash = new_hash;
+                src.len = 0;
+                return None;
+            }
+            if emptied && args.empty_means() == EmptyMeans::Delete && dst.path.exists() {
+                src.hash = new_hash;
+                src.len = 0;
+                match delete_emptied_peer(src.name, dst, args, passphrase) {
+                    Ok(()) => dst.deleted = true,
+                    Err(e) => sync_error(args.fail_fast, format_args!("Error deleting {}: {}", dst.name, e)),
+                }
+                return None;
+            }
+
+            // `dst.len` is the size of the content last synced between them.
+            if let Some(percent) = args.max_shrink_percent
+                && let Ok(len) = compress::len(&src.path)
+                && dst.path.exists()
+                && len < dst.len
+                && (dst.len - len) * 100 > dst.len * percent as u64
+            {
+                log_err!(
+                    "⚠️ Warning: File {} shrank from {} to {} bytes, by more than {}%. Not syncing it to {} (--max-shrink-percent).",
+                    src.name, dst.len, len, percent, dst.name
+                );
+                src.hash = new_hash;
+                src.len = len;
+                return None;
+            }
+
+            // Hard-linked files are one file; there's nothing to copy.
+            if args.copy_mode == CopyMode::Hardlink && fileops::same_file(&src.path, &dst.path) {
+                src.hash = new_hash.clone();
+                dst.hash = new_hash;
+                src.len = compress::len(&src.path).unwrap_or(src.len);
+                dst.len = src.len;
+                return None;
+            }
+
+            let len = compress::len(&src.path).unwrap_or(0);
+            if let Some(free) = short_on_space(args, &dst.path, len) {
+                // `src.hash` is left alone, so the next change retries the sync.
+                log_err!(
+                    "⚠️ Only {} MB free for {}, below --min-free-space. Skipping this sync.",
+                    free / (1024 * 1024), dst.name
+                );
+                return None;
+            }
+
+            if dst.path.exists() && compare_says_equal(args, &src.path, &dst.path) {
+                log_out!("🟰 File {} changed, but --compare-command says it still equals {}. Not syncing.", src.name, dst.name);
+                src.hash = new_hash;
+                src.len = compress::len(&src.path).unwrap_or(src.len);
+                return None;
+            }
+
+            let content = match content {
+                Some(bytes) => text_from_bytes(bytes),
+                None => read_text(&src.path),
+            };
+            if let Ok(text) = &content
+                && !passes_predicates(args, text)
+            {
+                // Only a further change to `src` is looked at again.
+                log_out!("⏭️ File {} changed, but skipped (predicate). {} is left as it is.", src.name, dst.name);
+                src.hash = new_hash;
+                src.len = text.len() as u64;
+                return None;
+            }
+
+            match args.hash_display.format(&new_hash) {
+                Some(shown) => log_out!("🔄 File {} changed ({}). Syncing to {}...", src.name, shown, dst.name),
+                None => log_out!("🔄 File {} changed. Syncing to {}...", src.name, dst.name),
+            }
+            src.hash = new_hash.clone();
+            match content {
+                Ok(content) => {
+                    src.len = content.len() as u64;
+                    let (content, normalized) = normalize_bom(args, content);
+                    match write_synced(src, dst, new_hash, &content, normalized, args) {
+                        Ok(()) => stats.record_sync(src.name, dst.name, content.len() as u64),
+                        Err(e) => sync_error(args.fail_fast, format_args!("Error writing {}: {}", dst.name, e)),
+                    }
+                }
+                Err(e) => sync_error(args.fail_fast, format_args!("❌ Skipping sync {} -> {}: {}", src.name, dst.name, e)),
+            }
+        }
+        Ok(FileState::Present(_)) => {
+            src.empty_since = None;
+            // Only the modification time changed, e.g. `touch`. The event
+            // for iyr's own write looks the same, so that one doesn't count.
+            if retouched && fileops::take_written_mtime(&src.path) != mtime {
+                stats.record_touch();
+                if !args.ignore_mtime_only_changes {
+                    log_out!("👆 File {} was touched, but its content didn't change. Nothing to sync.", src.name);
+                    logging::emit_json(serde_json::json!({ "type": "no_op", "side": src.name, "reason": "mtime_only" }));
+                }
+            }
+        }
+        Err(e) => sync_error(args.fail_fast, format_args!("Error reading {}: {}", src.name, e)),
+    }
+    None
 }
 
-// ----------------------
-// HELPER FUNCTIONS
-// ----------------------
+// Events are matched by file name within the watched directory, not by the
+// exact canonical path, so a file that is deleted and recreated under the
+// same name (a common way to "save") is still picked up as a change. With
+// --strict-paths only an exact path match counts.
+fn is_watched_path(path: &Path, watched: &Path, strict: bool) -> bool {
+    if path == watched {
+        return true;
+    }
+    if strict {
+        return false;
+    }
+    if path.file_name() == watched.file_name() {
+        return path.parent().and_then(|p| fs::canonicalize(p).ok()).as_deref() == watched.parent();
+    }
 
-fn compute_hash(path: &Path) -> std::io::Result<u32> {
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
-    let mut hasher = Hasher::new();
-    let mut buffer = [0; 8192];
+    // Case-insensitive filesystems (macOS, Windows) can report a name in
+    // another case than it was opened with. That's only the watched file if
+    // it resolves to it, which also keeps `Notes.txt` and `notes.txt` apart
+    // where case matters.
+    let lower = |p: &Path| p.file_name().map(|n| n.to_string_lossy().to_lowercase());
+    lower(path) == lower(watched)
+        && (fs::canonicalize(path).ok().as_deref() == Some(watched) || fileops::same_file(path, watched))
+}
 
-    loop {
-        let count = reader.read(&mut buffer)?;
-        if count == 0 { break; }
-        hasher.update(&buffer[..count]);    
+// Cloud clients drop a conflict copy next to a file when they can't merge
+// their own changes with ours. Those copies never match a watched path, so
+// they are never synced, but they usually mean something else is editing the
+// file and are worth pointing out.
+fn is_cloud_conflict_copy(path: &Path, watched: &Path, pattern: &Regex) -> bool {
+    if path == watched || path.parent() != watched.parent() {
+        return false;
     }
-    Ok(hasher.finalize())
+
+    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let stem = watched.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+
+    !stem.is_empty() && name.starts_with(stem) && pattern.is_match(name)
 }
 
-fn update_path(input: &str) -> PathBuf {
-    let path = PathBuf::from(input);
-    let parent = path.parent().unwrap_or(Path::new("."));
-    
-    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("file");
-    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("txt");
+// --print-config: lists every option with the value in effect and whether it
+// came from the command line, the environment or the built-in default.
+fn print_config(matches: &ArgMatches) {
+    let command = Cli::command();
+    let mut config = serde_json::Map::new();
+
+    log_out!("⚙️ Effective configuration:");
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        if matches!(id, "help" | "version") {
+            continue;
+        }
+
+        let name = match arg.get_long() {
+            Some(long) => format!("--{}", long),
+            None => id.to_uppercase(),
+        };
+        let values: Vec<String> = matches
+            .get_raw(id)
+            .map(|raw| raw.map(|v| v.to_string_lossy().into_owned()).collect())
+            .unwrap_or_default();
+        let source = match matches.value_source(id) {
+            Some(ValueSource::CommandLine) => "cli",
+            Some(ValueSource::EnvVariable) => "env",
+            Some(ValueSource::DefaultValue) => "default",
+            _ => "unset",
+        };
 
-    let new_filename = format!("{}_backup.{}", stem, ext);
-    parent.join(new_filename) 
+        if values.is_empty() {
+            log_out!("   {:<26} {:<24} ({})", name, "-", source);
+            config.insert(name, serde_json::json!({ "value": null, "source": source }));
+        } else {
+            log_out!("   {:<26} {:<24} ({})", name, values.join(", "), source);
+            config.insert(name, serde_json::json!({ "value": values.join(","), "source": source }));
+        }
+    }
+
+    logging::emit_json(serde_json::json!({ "type": "config", "options": config }));
 }
 
-// PHASE 1: Name & Extension Check
-fn compare_stem_ext(path_a: &Path, path_b: &Path) -> bool {
-    let name_a = path_a.file_name().and_then(|s| s.to_str()).unwrap_or("");
-    let name_b = path_b.file_name().and_then(|s| s.to_str()).unwrap_or("");
+fn log_hashes(label: &str, hash_a: &Checksum, hash_b: &Checksum, display: HashDisplay) {
+    if let (Some(a), Some(b)) = (display.format(hash_a), display.format(hash_b)) {
+        log_out!("📊 {} -> A: {}, B: {}", label, a, b);
+    }
+}
 
-    // 1. Check for empty filenames
-    if name_a.is_empty() || name_b.is_empty() {
-        eprintln!("❌ Error: Invalid file paths provided.");
-        return false;
+// --mirror-permissions-only: a metadata-only repair for files whose content
+// already matches. Returns the exit code.
+fn mirror_permissions(path_a: &Path, path_b: &Path, identical: bool) -> io::Result<i32> {
+    if !identical {
+        log_err!("❌ Files differ. Only the permissions of identical files are repaired (--mirror-permissions-only).");
+        logging::emit_error("compare", "files differ; permissions are only repaired for identical files", None);
+        return Ok(EXIT_DIFFER);
     }
 
-    // 2. Strict Name Equality (Case Insensitive)
-    if !name_a.eq_ignore_ascii_case(name_b) {
-        eprintln!("❌ Error: Files must have the same name and extension.");
-        eprintln!("   File A: {}", name_a);
-        eprintln!("   File B: {}", name_b);
-        return false;
+    let perms_a = fs::metadata(path_a)?.permissions();
+    let perms_b = fs::metadata(path_b)?.permissions();
+    let (shown_a, shown_b) = (fileops::describe_permissions(&perms_a), fileops::describe_permissions(&perms_b));
+    let repaired = perms_a != perms_b;
+    if repaired {
+        fs::set_permissions(path_b, perms_a)?;
+        log_out!("🔐 Content matches, but permissions differed (A: {}, B: {}). Copied A's permissions onto B; content untouched.", shown_a, shown_b);
+    } else {
+        log_out!("✅ Files are identical, permissions included ({}).", shown_a);
     }
-    
-    // 3. Extension Check
-    if path_a.extension().is_none() || path_b.extension().is_none() {
-         eprintln!("⚠️ Warning: One or both files have no extension.");
-         // We allow this, but warn.
+    logging::emit_json(serde_json::json!({
+        "type": "permissions",
+        "repaired": repaired,
+        "a": shown_a,
+        "b": shown_b,
+    }));
+    Ok(0)
+}
+
+// --verify: reports whether the pair is in sync and returns the exit code.
+fn report_verify(hash_a: &Checksum, hash_b: &Checksum) -> i32 {
+    let identical = hash_a == hash_b;
+    logging::emit_json(serde_json::json!({
+        "type": "verify",
+        "identical": identical,
+        "hash_a": format!("{:x}", hash_a),
+        "hash_b": format!("{:x}", hash_b),
+    }));
+
+    if identical {
+        log_out!("✅ Files are identical.");
+        0
+    } else {
+        log_err!("❌ Files differ.");
+        EXIT_DIFFER
+    }
+}
+
+// --dereference-on-write false: a watched path that is a symlink becomes a
+// regular file with the same content before anything is synced, so that no
+// write goes through to the link's target. The content is backed up first,
+// like anything else iyr replaces.
+fn replace_symlink(path: &Path, input: &str, name: &str, args: &Cli, passphrase: Option<&SecretString>) -> io::Result<()> {
+    let Ok(target) = fs::read_link(path) else {
+        return Ok(());
+    };
+    let backup = backup::create_backup(path, input, &args.backup_template(), passphrase, args.dedupe_backups)?;
+    fileops::dereference(path)?;
+    log_out!("🔗 {} was a symlink to {:?}. Replaced it with a regular file (--dereference-on-write false).", name, target);
+    log_out!("   Backup: {:?}", shown(&backup));
+    Ok(())
+}
+
+// --diff-command: hands a startup conflict to the user's own diff/merge tool
+// and waits for it to exit. Whatever it leaves behind is re-hashed and goes
+// through the normal rules, so a finished merge needs no --overwrite.
+fn open_diff_tool(diff: &CommandTemplate, path_a: &Path, path_b: &Path) -> io::Result<()> {
+    if !io::stdin().is_terminal() {
+        log_err!("⚠️ Warning: Not running --diff-command, stdin is not a terminal.");
+        return Ok(());
     }
 
-    true
+    log_out!("🔍 Files differ. Opening diff tool...");
+    let status = diff.run(&[("a", path_a), ("b", path_b)])?;
+    if !status.success() {
+        log_err!("⚠️ Warning: Diff tool exited with {}.", status);
+    }
+    Ok(())
 }
 
-// PHASE 2: Magic Number & Content Check
-fn is_valid_text_file(path: &Path) -> Result<bool, io::Error> {
-    if !path.is_file() {
-        return Ok(false);
+// --confirm-destructive: shows what the startup conflict is about to do, the
+// way --preflight words it, and asks once. Without a terminal to ask on the
+// answer is no, unless --yes. A no ends the run.
+fn confirm_destructive_or_exit(args: &Cli, len_a: u64, len_b: u64) {
+    let plan = args.rules().plan(true, len_a, len_b).describe(args.conflict);
+    if args.yes {
+        return;
     }
+    let confirmed = if io::stdin().is_terminal() {
+        eprint!("❓ About to {}. Continue? [y/N] ", plan);
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).is_ok() && matches!(answer.trim(), "y" | "Y" | "yes")
+    } else {
+        log_err!("⚠️ About to {}, but stdin is not a terminal to confirm it on. Pass --yes to go ahead.", plan);
+        false
+    };
+    if !confirmed {
+        log_err!("❌ Not confirmed. No files were changed.");
+        logging::emit_error("compare", "overwrite not confirmed (--confirm-destructive)", None);
+        exit(EXIT_DIFFER);
+    }
+}
 
-    let mut file = File::open(path)?;
-    let mut buffer = [0u8; 1024];
-    let bytes_read = file.read(&mut buffer)?;
-    
-    // Empty files are valid text files
-    if bytes_read == 0 {
-        return Ok(true);
+// By default a failed sync is logged and the watcher keeps going. With
+// --fail-fast it ends the run, so a supervisor (e.g. systemd) can restart us
+// rather than the same error repeating unnoticed.
+fn sync_error(fail_fast: bool, message: std::fmt::Arguments) {
+    log_err!("{}", message);
+    let text = message.to_string();
+    // Some messages lead with an emoji for the console; events don't need it.
+    logging::emit_error("sync", text.trim_start_matches(|c: char| !c.is_alphanumeric()), None);
+    report::record_error(&message.to_string());
+    if fail_fast {
+        log_err!("❌ Stopping on first sync error (--fail-fast).");
+        exit(EXIT_IO);
     }
+}
+
+// Dumps the stats on SIGUSR1 without stopping the watcher, so a long-running
+// instance can be inspected with `kill -USR1 <pid>`.
+#[cfg(unix)]
+fn watch_sigusr1(stats: Arc<Stats>) -> io::Result<()> {
+    use signal_hook::{consts::SIGUSR1, iterator::Signals};
+
+    let mut signals = Signals::new([SIGUSR1])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            stats.print_summary();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn watch_sigusr1(_stats: Arc<Stats>) -> io::Result<()> {
+    log_err!("⚠️ Warning: --summary-on-sigusr1 is only supported on Unix. Ignoring.");
+    Ok(())
+}
 
-    let head = &buffer[..bytes_read];
+// Set once the stop hook has run, so a signal arriving while the watcher
+// winds down on its own can't run it a second time.
+static STOP_HOOK_RAN: AtomicBool = AtomicBool::new(false);
 
-    if infer::get(head).is_some() {
-        return Ok(false);
+// --on-start-command / --on-stop-command: lifecycle hooks that run once per
+// run, with the pair in the environment. A failing hook is only reported.
+fn run_hook(hook: &CommandTemplate, event: &str, path_a: &Path, path_b: &Path, stats: &Stats) {
+    if event == "stop" && STOP_HOOK_RAN.swap(true, Ordering::SeqCst) {
+        return;
     }
 
-    match content_inspector::inspect(head) {
-        content_inspector::ContentType::UTF_8 |
-        content_inspector::ContentType::UTF_8_BOM => Ok(true),
-        content_inspector::ContentType::BINARY => Ok(false),
-        _ => Ok(false),
+    let mut command = hook.to_command(&[("a", path_a), ("b", path_b)]);
+    command
+        .env("IYR_EVENT", event)
+        .env("IYR_PATH_A", path_a)
+        .env("IYR_PATH_B", path_b)
+        .env("IYR_SYNCS", stats.syncs().to_string())
+        .env("IYR_CONFLICTS", stats.conflicts().to_string());
+    // Whatever the hook prints mustn't end up among the JSON.
+    if logging::stdout_is_machine() {
+        command.stdout(io::stderr());
     }
+
+    match command.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => log_err!("⚠️ Warning: --on-{}-command exited with {}.", event, status),
+        Err(e) => log_err!("⚠️ Warning: Could not run --on-{}-command: {}", event, e),
+    }
+}
+
+// With an --on-stop-command or --report-file, SIGINT and SIGTERM shut down
+// gracefully: the hook runs and the report is written, then the process exits
+// 0. Without either they keep their default behavior.
+#[cfg(unix)]
+fn stop_on_signal(hook: Option<CommandTemplate>, paths: (PathBuf, PathBuf), stats: Arc<Stats>) -> io::Result<()> {
+    use signal_hook::{consts::{SIGINT, SIGTERM}, iterator::Signals};
+
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    std::thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            log_out!("⏹️ Shutting down...");
+            if let Some(hook) = &hook {
+                run_hook(hook, "stop", &paths.0, &paths.1, &stats);
+            }
+            exit(0);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn stop_on_signal(_hook: Option<CommandTemplate>, _paths: (PathBuf, PathBuf), _stats: Arc<Stats>) -> io::Result<()> {
+    log_err!("⚠️ Warning: --on-stop-command and --report-file only run when the watcher ends on its own on this platform.");
+    Ok(())
+}
+
+// Forces a reconciliation pass on SIGUSR2, as if both files had changed. If
+// `kill -USR2` fixes a pair that edits didn't, the problem is in event
+// delivery rather than in the sync logic. The empty batch only wakes the
+// event loop; `requested` says why.
+#[cfg(unix)]
+fn watch_sigusr2(tx: Sender<DebounceEventResult>, requested: Arc<AtomicBool>) -> io::Result<()> {
+    use signal_hook::{consts::SIGUSR2, iterator::Signals};
+
+    let mut signals = Signals::new([SIGUSR2])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            requested.store(true, Ordering::Relaxed);
+            if tx.send(Ok(Vec::new())).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn watch_sigusr2(_tx: Sender<DebounceEventResult>, _requested: Arc<AtomicBool>) -> io::Result<()> {
+    log_err!("⚠️ Warning: --reconcile-on-sigusr2 is only supported on Unix. Ignoring.");
+    Ok(())
 }
 
 // ----------------------
 // MAIN APPLICATION
 // ----------------------
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Cli::parse();
-    
-    let path_a = fs::canonicalize(&args.path_a).expect("File A must exist");
-    let path_b = fs::canonicalize(&args.path_b).expect("File B must exist");
+// --create-dirs: lets B point into a directory layout that doesn't exist
+// yet. Creates the missing parents and an empty B, which first-run seeding
+// then fills from A.
+fn create_destination(path: &Path) -> io::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+        && !parent.exists()
+    {
+        fs::create_dir_all(parent)?;
+        log_out!("📁 Created directory {:?}", parent);
+    }
+
+    fs::File::create_new(path)?;
+    log_out!("📁 Created empty file {:?}", path);
+    Ok(())
+}
+
+fn canonicalize_or_exit(input: &str, name: &str, follow_symlinks: bool) -> PathBuf {
+    let path = match follow_symlinks {
+        true => fs::canonicalize(input),
+        false => fileops::absolutize(Path::new(input)),
+    };
+    match path {
+        Ok(path) => path,
+        Err(e) => {
+            log_err!("❌ Error: File {} ({}) can't be opened: {}", name, input, e);
+            logging::emit_error("open", &e.to_string(), Some(Path::new(input)));
+            exit(EXIT_USAGE);
+        }
+    }
+}
+
+fn ensure_regular_file(path: &Path) -> io::Result<()> {
+    let mut report = ValidationReport::default();
+    if !report.check_regular_file(path)? {
+        logging::emit_json(report.to_json());
+        report.emit_errors();
+        exit(EXIT_VALIDATION);
+    }
+    Ok(())
+}
+
+// --expect-hash: checks a single file against a known checksum instead of
+// syncing a pair. Returns the exit code.
+fn check_expected_hash(path: &Path, expected: &str, args: &Cli) -> io::Result<i32> {
+    ensure_regular_file(path)?;
+
+    let actual = compute_hash(path, &args.hash_options())?;
+    if actual.matches_hex(expected) {
+        match args.hash_display.format(&actual) {
+            Some(shown) => log_out!("✅ Checksum matches ({}: {})", args.hash_algo, shown),
+            None => log_out!("✅ Checksum matches ({})", args.hash_algo),
+        }
+        Ok(0)
+    } else {
+        match args.hash_display.format(&actual) {
+            Some(shown) => log_err!("❌ Checksum mismatch! Expected {}, got {} ({})", expected, shown, args.hash_algo),
+            None => log_err!("❌ Checksum mismatch! ({})", args.hash_algo),
+        }
+        Ok(EXIT_DIFFER)
+    }
+}
+
+// notify-debouncer-full expires every raw event on its own once it's older
+// than the timeout, checking on each tick, so a file that never stops
+// changing still flushes. The worst-case delay is timeout + tick; with
+// --debounce-max-wait the tick is chosen to keep that within the cap.
+fn debounce_tick(args: &Cli) -> Option<Duration> {
+    let max_wait = args.debounce_max_wait?;
+    if max_wait <= args.debounce_ms {
+        log_err!(
+            "❌ Error: --debounce-max-wait ({}ms) must be larger than --debounce-ms ({}ms).",
+            max_wait, args.debounce_ms
+        );
+        logging::emit_error("usage", "--debounce-max-wait must be larger than --debounce-ms", None);
+        exit(EXIT_USAGE);
+    }
+
+    // The debouncer rejects a tick longer than its timeout.
+    let tick = (max_wait - args.debounce_ms).min(args.debounce_ms).max(1);
+    Some(Duration::from_millis(tick))
+}
+
+// --trash changes how a file is deleted, so it needs one of the options
+// that delete.
+fn check_trash(args: &Cli) {
+    if args.trash && !args.mirror_deletes && args.empty_means() != EmptyMeans::Delete {
+        log_err!("❌ Error: --trash needs --mirror-deletes or --empty-means delete.");
+        logging::emit_error("usage", "--trash needs --mirror-deletes or --empty-means delete", None);
+        exit(EXIT_USAGE);
+    }
+}
+
+// Every exit goes through here, so the final report sees how the run ended.
+fn exit(code: i32) -> ! {
+    report::finish(code);
+    std::process::exit(code)
+}
+
+fn main() {
+    if let Err(e) = run() {
+        log_err!("❌ Error: {}", e);
+        logging::emit_error("run", &e.to_string(), None);
+        report::record_error(&e.to_string());
+        exit(EXIT_IO);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Cli::command().get_matches();
+    let args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let stats = Arc::new(Stats::new());
+
+    logging::set_json(args.json || args.json_pretty, args.json_pretty);
+    fileops::set_fsync(!args.no_fsync);
+    backup::set_verify(args.verify_backups);
+    validate::set_strict_text(args.strict_text);
+    fileops::set_track_inodes(args.track_inode.is_some());
+
+    if args.print_config {
+        print_config(&matches);
+        return Ok(());
+    }
+
+    if let Some(path) = &args.tee_log {
+        logging::init_tee(path.clone(), args.log_max_size)?;
+    }
+
+    if let Some(id) = &args.pair_id {
+        logging::set_pair_id(id.clone());
+    }
+
+    if let Some(base) = &args.relative_display {
+        let base = match base {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir()?,
+        };
+        // Watched paths are canonical, so the base has to be too.
+        match fs::canonicalize(&base) {
+            Ok(base) => logging::set_display_base(base),
+            Err(e) => {
+                log_err!("❌ Error: --relative-display base {:?} can't be opened: {}", base, e);
+                logging::emit_error("usage", &e.to_string(), Some(&base));
+                exit(EXIT_USAGE);
+            }
+        }
+    }
+
+    if let Some(path) = &args.report_file {
+        report::init(report::Sink::File(path.clone()));
+    } else if args.summary_json_stdout {
+        logging::reserve_stdout();
+        report::init(report::Sink::Stdout);
+    }
+
+    let tick = debounce_tick(&args);
+    check_trash(&args);
+
+    if let Some(Commands::Restore { backup, target }) = &args.command {
+        let bytes = backup::restore_backup(backup, target)?;
+        log_out!("♻️ Restored {:?} -> {:?} ({} bytes)", backup, target, bytes);
+        return Ok(());
+    }
+
+    if let Some(Commands::Snapshot { name, path_a, path_b, dir }) = &args.command {
+        let saved = snapshot::create(dir, name, path_a, path_b)?;
+        log_out!("📸 Snapshot {:?} saved to {:?}", name, saved);
+        return Ok(());
+    }
+
+    if let Some(Commands::RestoreSnapshot { name, dir }) = &args.command {
+        for path in snapshot::restore(dir, name)? {
+            log_out!("♻️ Restored {:?} from snapshot {:?}", shown(&path), name);
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::BenchHash { file }) = &args.command {
+        bench::run(file)?;
+        return Ok(());
+    }
+
+    if let Some(list) = &args.batch {
+        exit(batch::run(list, &args.hash_options(), args.preflight.then(|| args.rules()).as_ref())?);
+    }
+
+    // Only needed for a conflict, but asked for up front so a prompt doesn't
+    // appear halfway through startup.
+    let passphrase = if args.encrypt_backups { Some(backup::read_passphrase()?) } else { None };
+
+    let input_a = args.path_a.as_deref().expect("clap requires PATH_A without a subcommand");
+    let path_a = canonicalize_or_exit(input_a, "A", args.follow_symlinks());
+
+    // No-op when --pair-id was given: the first label set wins.
+    if let Some(name) = path_a.file_name() {
+        logging::set_pair_id(name.to_string_lossy().into_owned());
+    }
+
+    if let Some(expected) = &args.expect_hash {
+        exit(check_expected_hash(&path_a, expected, &args)?);
+    }
+
+    let input_b = args.path_b.as_deref().expect("clap requires PATH_B without --expect-hash");
+    if args.create_dirs {
+        create_destination(Path::new(input_b))?;
+    }
+    let path_b = canonicalize_or_exit(input_b, "B", args.follow_symlinks());
+    if let Some(compression) = args.decompress_b {
+        compress::set_compressed(&path_b, compression);
+    }
 
     let parent_a = path_a.parent().expect("File A has no parent directory");
     let parent_b = path_b.parent().expect("File B has no parent directory");
 
-    println!("🔗 Linking: {:?} <==> {:?}", path_a, path_b);
+    log_out!("🔗 Linking: {:?} <==> {:?}", shown(&path_a), shown(&path_b));
+    report::set_pair(&path_a, &path_b, args.hash_algo);
+
+    // Each phase only runs once the previous one passed.
+    let mut report = ValidationReport::default();
+    let regular = report.check_regular_file(&path_a)? & report.check_regular_file(&path_b)?;
+
+    if regular && report.check_names(&path_a, &path_b) {
+        let text = report.check_text(&path_a)? & report.check_text(&path_b)?;
+        if !text {
+            log_err!("This tool only supports text-based files.");
+        }
+    }
+
+    logging::emit_json(report.to_json());
+    if !report.passed() {
+        report.emit_errors();
+        exit(EXIT_VALIDATION);
+    }
+    log_out!("✅ File Validation Passed (Text-only verified)");
+
+    // A default (empty) checksum never equals a real one, so the first
+    // change to either file is synced as usual.
+    let (mut hash_a, mut hash_b) = if args.quick_check && same_size_and_mtime(&path_a, &path_b) {
+        log_out!("⚡ Same size and modification time (--quick-check). Assuming the files are identical, not hashing them.");
+        (Checksum::default(), Checksum::default())
+    } else {
+        let hashes = (compute_hash(&path_a, &args.hash_options())?, compute_hash(&path_b, &args.hash_options())?);
+        log_hashes("Initial Hashes", &hashes.0, &hashes.1, args.hash_display);
+        hashes
+    };
+
+    // Everything up to here only opens the files for reading, which is what
+    // makes --verify safe to run against files that must not change.
+    if args.verify {
+        exit(report_verify(&hash_a, &hash_b));
+    }
+
+    if hash_a != hash_b
+        && let Some(diff) = &args.diff_command
+        && startup_len(&path_a, "A") > 0
+        && startup_len(&path_b, "B") > 0
+    {
+        open_diff_tool(diff, &path_a, &path_b)?;
+        hash_a = compute_hash(&path_a, &args.hash_options())?;
+        hash_b = compute_hash(&path_b, &args.hash_options())?;
+        log_hashes("Hashes after diff tool", &hash_a, &hash_b, args.hash_display);
+    }
+
+    let mut differ = hash_a != hash_b;
+    if differ && compare_says_equal(&args, &path_a, &path_b) {
+        log_out!("🟰 Files differ byte for byte, but --compare-command says they are equal.");
+        differ = false;
+    }
 
-    if !compare_stem_ext(&path_a, &path_b) {
-        std::process::exit(1);
+    if args.preflight {
+        let action = args.rules().plan(differ, startup_len(&path_a, "A"), startup_len(&path_b, "B"));
+        log_out!("🧭 Preflight (nothing was written): would {}.", action.describe(args.conflict));
+        logging::emit_json(serde_json::json!({ "type": "preflight", "a": path_a, "b": path_b, "plan": action.to_json() }));
+        let mut totals = preflight::Totals::default();
+        totals.add(&action);
+        exit(totals.exit_code());
     }
 
-    if !is_valid_text_file(&path_a)? || !is_valid_text_file(&path_b)? {
-        eprintln!("❌ Error: One of the files is detected as Binary (Image/Video/Exec).");
-        eprintln!("This tool only supports text-based files.");
-        std::process::exit(1);
+    if args.mirror_permissions_only {
+        exit(mirror_permissions(&path_a, &path_b, !differ)?);
     }
-    println!("✅ File Validation Passed (Text-only verified)");
 
-    let mut hash_a = compute_hash(&path_a).unwrap_or(0);
-    let mut hash_b = compute_hash(&path_b).unwrap_or(0);
+    if !args.dereference_on_write {
+        replace_symlink(&path_a, input_a, "A", &args, passphrase.as_ref())?;
+        replace_symlink(&path_b, input_b, "B", &args, passphrase.as_ref())?;
+    }
 
-    println!("📊 Initial Hashes -> A: {:x}, B: {:x}", hash_a, hash_b);
+    if differ {
+        let len_a = startup_len(&path_a, "A");
+        let len_b = startup_len(&path_b, "B");
 
-    if hash_a != hash_b {
-        if !args.overwrite {
-             eprintln!("❌ Files differ! Use '--overwrite' to sync them (creates backups).");
-             std::process::exit(1);
+        // The user's resolver decides, so this needs no --overwrite either.
+        if len_a > 0 && len_b > 0 && let Some(cmd) = &args.reconcile_command {
+            if args.confirm_destructive {
+                confirm_destructive_or_exit(&args, len_a, len_b);
+            }
+            log_out!("⚠️ Conflict! Both files have content. Running --reconcile-command...");
+            stats.record_conflict();
+            match reconcile(cmd, &path_a, &path_b, &args, passphrase.as_ref()) {
+                Ok(content) => {
+                    log_out!("   Reconciled. Wrote {} bytes to both files.", content.len());
+                    stats.record_sync("reconcile", "A", content.len() as u64);
+                    stats.record_sync("reconcile", "B", content.len() as u64);
+                    hash_a = compute_hash(&path_a, &args.hash_options())?;
+                    hash_b = hash_a.clone();
+                }
+                Err(e) => {
+                    log_err!("❌ Error: Could not reconcile the files: {}", e);
+                    logging::emit_error("reconcile", &e.to_string(), None);
+                    exit(EXIT_DIFFER);
+                }
+            }
         }
+        // Nothing gets overwritten, so this needs no --overwrite.
+        else if len_a > 0 && len_b > 0 && args.conflict == ConflictStrategy::KeepBoth {
+            log_out!("⚠️ Conflict! Both files have content. Keeping both (keep-both)...");
+            stats.record_conflict();
+            let (copy_a, copy_b) = keep_both(&path_a, &path_b)?;
+            log_out!("   Conflict copies: {:?}, {:?}", shown(&copy_a), shown(&copy_b));
+            if args.notify_on_conflict {
+                desktop::notify_kept_both(&copy_a, &copy_b);
+            }
+        }
+        else if len_a > 0 && len_b > 0 {
+            if !args.overwrite {
+                log_err!("❌ Files differ! Use '--overwrite' to sync them (creates backups).");
+                logging::emit_error("compare", "files differ; pass --overwrite to sync them", None);
+                exit(EXIT_DIFFER);
+            }
+            if args.confirm_destructive {
+                confirm_destructive_or_exit(&args, len_a, len_b);
+            }
+
+            log_out!("⚠️ Conflict! Both files have content. Backing up and clearing...");
+            stats.record_conflict();
 
-        let len_a = fs::metadata(&path_a).unwrap().len();
-        let len_b = fs::metadata(&path_b).unwrap().len();
+            let a_wins = args.conflict.a_wins(len_a, len_b);
+            let (src, dst, from, to) = if a_wins { (&path_a, &path_b, "A", "B") } else { (&path_b, &path_a, "B", "A") };
 
-        if len_a > 0 && len_b > 0 {
-            println!("⚠️ Conflict! Both files have content. Backing up and clearing...");
-            
-            let content_a = fs::read_to_string(&path_a).unwrap_or_default();
-            let content_b = fs::read_to_string(&path_b).unwrap_or_default();
+            // Clones copy raw bytes, so make sure the winner is text all the
+            // way through before it lands on the other side.
+            read_text_or_exit(src);
 
             // Create Backups
-            fs::write(update_path(&args.path_a), &content_a)?;
-            fs::write(update_path(&args.path_b), &content_b)?;
-
-            // Decision: Sync A to B (Arbitrary choice for conflict resolution)
-            println!("   Syncing A -> B");
-            fs::write(&path_b, &content_a)?;
-            
-            hash_b = hash_a;
-        } 
-        else if len_a > 0 && len_b == 0 {
-            println!("📥 B is empty. Syncing A -> B");
-            let content_a = fs::read_to_string(&path_a).unwrap_or_default();
-            fs::write(&path_b, &content_a)?; 
-            hash_b = hash_a;
-        } 
-        else if len_b > 0 && len_a == 0 {
-            println!("📥 A is empty. Syncing B -> A");
-            let content_b = fs::read_to_string(&path_b).unwrap_or_default();
-            fs::write(&path_a, &content_b)?;
-            hash_a = hash_b;
+            let backup_a = backup::create_backup(&path_a, input_a, &args.backup_template(), passphrase.as_ref(), args.dedupe_backups)?;
+            let backup_b = backup::create_backup(&path_b, input_b, &args.backup_template(), passphrase.as_ref(), args.dedupe_backups)?;
+            log_out!("   Backups: {:?}, {:?}", shown(&backup_a), shown(&backup_b));
+
+            log_out!("   Syncing {} -> {} ({})", from, to, args.conflict);
+            let bytes = fileops::copy_over(src, dst)?;
+            stats.record_sync(from, to, bytes);
+            preserve_xattrs(&args, src, dst);
+
+            if args.notify_on_conflict {
+                desktop::notify_conflict(src, dst, if a_wins { &backup_b } else { &backup_a });
+            }
+
+            // Normalizing writes to the winner too, which a read-only peer
+            // mustn't see.
+            let normalize = a_wins || args.read_only_peer.is_none();
+            if normalize && let Some(hash) = normalize_bom_at_startup(src, dst, &args)? {
+                (hash_a, hash_b) = (hash.clone(), hash);
+            } else if a_wins {
+                hash_b = hash_a.clone();
+            } else {
+                hash_a = hash_b.clone();
+            }
         }
-    } else {
-        println!("✅ Files are identical.");
+        // First-run seeding: exactly one side is empty, so copying the other
+        // onto it can't lose anything and needs neither --overwrite nor a backup.
+        else if args.no_seed {
+            log_out!("⏭️ One file is empty. Leaving it as-is (--no-seed).");
+        }
+        else if len_a > 0 {
+            log_out!("📥 B is empty. Seeding A -> B");
+            read_text_or_exit(&path_a);
+            let bytes = fileops::copy_over(&path_a, &path_b)?;
+            stats.record_sync("A", "B", bytes);
+            preserve_xattrs(&args, &path_a, &path_b);
+            if let Some(hash) = normalize_bom_at_startup(&path_a, &path_b, &args)? {
+                hash_a = hash;
+            }
+            hash_b = hash_a.clone();
+        }
+        else if args.read_only_peer.is_some() {
+            log_out!("⏭️ A is empty. Not seeding it from B, which is a read-only peer.");
+        }
+        else {
+            log_out!("📥 A is empty. Seeding B -> A");
+            read_text_or_exit(&path_b);
+            let bytes = fileops::copy_over(&path_b, &path_a)?;
+            stats.record_sync("B", "A", bytes);
+            preserve_xattrs(&args, &path_b, &path_a);
+            if let Some(hash) = normalize_bom_at_startup(&path_b, &path_a, &args)? {
+                hash_b = hash;
+            }
+            hash_a = hash_b.clone();
+        }
+    } else if hash_a == hash_b {
+        log_out!("✅ Files are identical.");
     }
 
-    println!("👀 Starting watcher...");
+    if args.copy_mode == CopyMode::Hardlink {
+        link_pair(&path_a, &path_b, input_b, hash_a == hash_b, &args, passphrase.as_ref());
+    }
+
+    if args.summary_on_sigusr1 {
+        watch_sigusr1(Arc::clone(&stats))?;
+    }
+
+    log_out!("👀 Starting watcher...");
 
     let (tx, rx) = channel();
-    let mut debouncer = new_debouncer(Duration::from_millis(500), None, tx)?;
+    let reconcile = Arc::new(AtomicBool::new(false));
+    if args.reconcile_on_sigusr2 {
+        watch_sigusr2(tx.clone(), Arc::clone(&reconcile))?;
+    }
+    let timeout = Duration::from_millis(args.debounce_ms);
+    let mut debouncer = new_debouncer(timeout, tick, tx)?;
+
+    match args.watch_mode {
+        WatchMode::Parent => {
+            debouncer.watch(parent_a, RecursiveMode::NonRecursive)?;
+            if parent_a != parent_b {
+                debouncer.watch(parent_b, RecursiveMode::NonRecursive)?;
+            }
+        }
+        WatchMode::File => {
+            for (path, parent) in [(&path_a, parent_a), (&path_b, parent_b)] {
+                if let Err(e) = debouncer.watch(path, RecursiveMode::NonRecursive) {
+                    log_err!("⚠️ Warning: Can't watch {:?} directly ({}). Watching its directory instead.", shown(path), e);
+                    debouncer.watch(parent, RecursiveMode::NonRecursive)?;
+                }
+            }
+        }
+    }
+
+    let mut breaker = args.max_events_per_sec.map(CircuitBreaker::new);
+    let mut ignore = Some(IgnoreSet::new(&args.ignore_patterns)?);
+    if let Some(set) = &ignore
+        && let Some(path) = [&path_a, &path_b].into_iter().find(|p| set.matches(p))
+    {
+        log_err!("⚠️ Warning: {:?} matches an --ignore pattern. Not ignoring any events.", shown(path));
+        ignore = None;
+    }
+    // Backups are named after the path as it was given at startup, and after
+    // the canonical path while watching.
+    let own_files_pattern = args.own_files_pattern(&[Path::new(input_a), Path::new(input_b), &path_a, &path_b])?;
+    let is_own_file = |path: &Path| path.file_name().is_some_and(|n| own_files_pattern.is_match(&n.to_string_lossy()));
+    let mut own_files = true;
+    if let Some(path) = [&path_a, &path_b].into_iter().find(|p| is_own_file(p)) {
+        log_err!("⚠️ Warning: {:?} matches --backup-ignore-pattern. Not dropping events for backups.", shown(path));
+        own_files = false;
+    }
+    let mut paused = false;
+    // Set once --max-conflicts is reached. Only cleared by a restart.
+    let mut halted = false;
+    // --conflict keep-both: our own conflict copies, which aren't a cloud
+    // client's.
+    let mut kept_copies: Vec<PathBuf> = Vec::new();
+    // --copy-mode hardlink: whether B is still a hard link to A.
+    let mut linked = fileops::same_file(&path_a, &path_b);
+    let mut a = Side::new("A", path_a, hash_a);
+    let mut b = Side::new("B", path_b, hash_b);
+    if args.track_inode.is_some() && !cfg!(unix) {
+        log_err!("⚠️ Warning: --track-inode is only supported on Unix. Ignoring.");
+    }
+    // Set while a truncated file is waiting to settle.
+    let mut recheck_at: Option<Instant> = None;
+    let deep_check_every = args.verify_after_watch.map(Duration::from_secs);
+    let mut next_deep_check = deep_check_every.map(|every| Instant::now() + every);
+    let heartbeat_every = args.heartbeat.map(Duration::from_secs);
+    let mut next_heartbeat = heartbeat_every.map(|every| Instant::now() + every);
+    // --watch-delay: events are recorded but not acted on until then.
+    let mut hold_until = args.watch_delay.map(|secs| Instant::now() + Duration::from_secs(secs));
+    // --sync-window: when it next opens, while changes are waiting for it.
+    let mut window_opens: Option<Instant> = None;
+    if let Some(secs) = args.watch_delay {
+        log_out!("⏳ Letting the files settle for {}s before syncing (--watch-delay)...", secs);
+    }
 
-    debouncer.watch(parent_a, RecursiveMode::NonRecursive)?;
-    if parent_a != parent_b {
-        debouncer.watch(parent_b, RecursiveMode::NonRecursive)?;
+    if args.on_stop_command.is_some() || args.report_file.is_some() || args.summary_json_stdout {
+        stop_on_signal(args.on_stop_command.clone(), (a.path.clone(), b.path.clone()), Arc::clone(&stats))?;
+    }
+    if let Some(hook) = &args.on_start_command {
+        run_hook(hook, "start", &a.path, &b.path, &stats);
     }
 
     // 6. Event Loop
-    for result in rx {
+    loop {
+        // While paused, settling or holding off, wake up when that's due to
+        // end even if no more events arrive, so pending changes still get synced.
+        let wake_at = [breaker.as_ref().and_then(|b| b.open_until()), recheck_at, next_deep_check, next_heartbeat, hold_until, window_opens]
+            .into_iter()
+            .flatten()
+            .min();
+        let result = match wake_at {
+            Some(at) => match rx.recv_timeout(at.saturating_duration_since(Instant::now())) {
+                Ok(result) => result,
+                Err(RecvTimeoutError::Timeout) => Ok(Vec::new()),
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(result) => result,
+                Err(_) => break,
+            },
+        };
+
+        // Before anything that can `continue`, so holding off syncs doesn't
+        // silence it.
+        if let (Some(at), Some(every)) = (next_heartbeat, heartbeat_every)
+            && Instant::now() >= at
+        {
+            heartbeat(&a, &b, &args, halted);
+            next_heartbeat = Some(Instant::now() + every);
+        }
+
         match result {
             Ok(events) => {
-                let mut check_a = false;
-                let mut check_b = false;
+                // Events only say a file was touched. What to do is decided
+                // from each file's state once the whole batch is in, so a
+                // modify followed by a delete is handled as a delete.
+                let mut relevant = 0;
+
+                stats.record_events(events.len() as u64);
+
+                if reconcile.swap(false, Ordering::Relaxed) {
+                    log_out!("🔁 Reconciling on request (SIGUSR2)...");
+                    a.pending = true;
+                    b.pending = true;
+                }
 
                 for event in events {
+                    if args.debounce_debug {
+                        log_out!(
+                            "🐛 Event {:?} {:?} (raw event {}ms ago)",
+                            event.kind,
+                            event.paths,
+                            event.time.elapsed().as_millis()
+                        );
+                    }
 
-                    if let EventKind::Modify(_) = event.kind {
-                         for path in &event.paths {
-                            if path == &path_a { check_a = true; }
-                            if path == &path_b { check_b = true; }
-                        }
+                    if ignore.as_ref().is_some_and(|set| set.ignores(&event)) {
+                        continue;
+                    }
+                    // Iyr's own backups, even ones from an earlier run.
+                    if own_files && !event.paths.is_empty() && event.paths.iter().all(|p| is_own_file(p)) {
+                        continue;
                     }
-                }
 
-                if check_a {
-                    if let Ok(new_hash) = compute_hash(&path_a) {
-                        if new_hash != hash_a {
-                            println!("🔄 File A changed ({:x}). Syncing to B...", new_hash);
-                            hash_a = new_hash; 
-                            if let Ok(content) = fs::read_to_string(&path_a) {
-                                if let Err(e) = fs::write(&path_b, content) {
-                                    eprintln!("Error writing B: {}", e);
-                                } else {
-                                    hash_b = new_hash; 
+                    if let EventKind::Create(_) = event.kind {
+                        for path in &event.paths {
+                            for watched in [&a.path, &b.path] {
+                                if is_cloud_conflict_copy(path, watched, &args.cloud_conflict_pattern) && !kept_copies.contains(path) {
+                                    log_out!("☁️ Cloud conflict copy {:?} appeared next to {:?}. Ignoring it.", shown(path), shown(watched));
                                 }
                             }
                         }
                     }
+
+                    if let EventKind::Modify(_) | EventKind::Create(_) | EventKind::Remove(_) = event.kind {
+                        let hit_a = event.paths.iter().any(|p| is_watched_path(p, &a.path, args.strict_paths));
+                        let hit_b = event.paths.iter().any(|p| is_watched_path(p, &b.path, args.strict_paths));
+                        a.pending |= hit_a;
+                        b.pending |= hit_b;
+                        if hit_a || hit_b { relevant += 1; }
+                    }
+                }
+
+                if let Some(breaker) = breaker.as_mut() {
+                    let now = Instant::now();
+                    if breaker.record(relevant, now) {
+                        log_err!(
+                            "⚠️ Event storm: over {} events/s for this pair. Pausing syncing...",
+                            args.max_events_per_sec.unwrap_or_default()
+                        );
+                        paused = true;
+                    }
+                    if breaker.is_open(now) {
+                        continue;
+                    }
+                    if paused {
+                        log_out!("▶️ Event storm is over. Resuming syncing.");
+                        paused = false;
+                    }
+                }
+
+                if let Some(until) = hold_until {
+                    if Instant::now() < until {
+                        continue;
+                    }
+                    hold_until = None;
+                    if a.pending || b.pending {
+                        log_out!("▶️ Watch delay is over. Syncing the settled state...");
+                    }
+                }
+
+                if let Some(window) = args.sync_window {
+                    let wait = window.until_open(chrono::Local::now().time());
+                    if !wait.is_zero() {
+                        if (a.pending || b.pending) && window_opens.is_none() {
+                            log_out!("🕘 Outside the sync window ({}). Holding changes until it opens.", window);
+                        }
+                        // Pending flags only ever say "look again", so
+                        // however many changes pile up, each file syncs once.
+                        window_opens = (a.pending || b.pending).then(|| Instant::now() + wait);
+                        continue;
+                    }
+                    if window_opens.take().is_some() {
+                        log_out!("🕘 The sync window is open. Syncing the latest state...");
+                    }
                 }
 
-                if check_b {
-                    if let Ok(new_hash) = compute_hash(&path_b) {
-                        if new_hash != hash_b {
-                            println!("🔄 File B changed ({:x}). Syncing to A...", new_hash);
-                            hash_b = new_hash;
+                // Saving by rename gives a file a new inode, so the link is
+                // gone. From then on the pair is synced by copying.
+                if linked && !fileops::same_file(&a.path, &b.path) {
+                    log_err!("⚠️ Warning: A and B are no longer hard-linked (an editor probably saved by rename). Syncing by copying instead.");
+                    linked = false;
+                }
 
-                            if let Ok(content) = fs::read_to_string(&path_b) {
-                                if let Err(e) = fs::write(&path_a, content) {
-                                    eprintln!("Error writing A: {}", e);
-                                } else {
-                                    hash_a = new_hash;
-                                }
-                            }
+                if let Some(policy) = args.track_inode {
+                    for side in [&mut a, &mut b] {
+                        if side.pending {
+                            check_inode(side, policy);
                         }
                     }
                 }
+
+                if halted {
+                    for side in [&mut a, &mut b] {
+                        report_while_halted(side, &args);
+                    }
+                }
+
+                if a.pending && b.pending {
+                    kept_copies.extend(resolve_concurrent_edit(&mut a, &mut b, &args, passphrase.as_ref(), &stats));
+                }
+
+                recheck_at = None;
+                if b.pending
+                    && let Some(mode) = args.read_only_peer
+                {
+                    recheck_at = guard_peer(&mut b, &a, mode, &args, passphrase.as_ref(), &stats);
+                }
+                if a.pending
+                    && let Some(at) = handle_change(&mut a, &mut b, &args, passphrase.as_ref(), &stats)
+                {
+                    recheck_at = Some(recheck_at.map_or(at, |r| r.min(at)));
+                }
+                // A read-only peer is only ever reverted, never synced from.
+                if b.pending
+                    && args.read_only_peer.is_none()
+                    && let Some(at) = handle_change(&mut b, &mut a, &args, passphrase.as_ref(), &stats)
+                {
+                    recheck_at = Some(recheck_at.map_or(at, |r| r.min(at)));
+                }
+
+                // Every write iyr makes is a rename, so a synced file has a
+                // new inode that mustn't be taken for someone else's. Only
+                // inodes iyr wrote itself are taken on.
+                if args.track_inode.is_some() {
+                    a.remember_inode();
+                    b.remember_inode();
+                }
+
+                if !halted
+                    && let Some(max) = args.max_conflicts
+                    && stats.conflicts() >= max
+                {
+                    halt_syncing(&a, &b, &args, &stats);
+                    halted = true;
+                    recheck_at = None;
+                }
+
+                // A file watch follows the inode, and saving by rename (which
+                // iyr itself does) swaps it out. Re-registering the paths
+                // picks up the new files; it's a no-op if nothing changed.
+                if args.watch_mode == WatchMode::File {
+                    for side in [&a, &b] {
+                        let _ = debouncer.watch(&side.path, RecursiveMode::NonRecursive);
+                    }
+                }
             },
-            Err(e) => println!("Watch error: {:?}", e),
+            Err(e) => log_out!("Watch error: {:?}", e),
         }
+
+        // A file that is still settling or waiting out a storm is expected
+        // to differ, so the deep check waits until both sides are idle.
+        if let (Some(at), Some(every)) = (next_deep_check, deep_check_every)
+            && Instant::now() >= at
+            && hold_until.is_none()
+            && !halted
+            && args.sync_window.is_none_or(|w| w.is_open(chrono::Local::now().time()))
+            && !a.pending
+            && !b.pending
+        {
+            deep_check(&mut a, &mut b, &args, &stats);
+            if args.track_inode.is_some() {
+                b.remember_inode();
+            }
+            next_deep_check = Some(Instant::now() + every);
+        }
+    }
+
+    if let Some(hook) = &args.on_stop_command {
+        run_hook(hook, "stop", &a.path, &b.path, &stats);
     }
+    report::finish(0);
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A watched file, canonical as the event loop holds it, in a fresh dir.
+    fn watched() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let path = fs::canonicalize(dir.path()).unwrap().join("notes.txt");
+        fs::write(&path, "notes\n").unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn non_canonical_event_path_matches() {
+        let (dir, path) = watched();
+        let event = dir.path().join("sub").join("..").join("notes.txt");
+        assert!(is_watched_path(&event, &path, false));
+        assert!(!is_watched_path(&event, &path, true));
+    }
+
+    #[test]
+    fn other_case_matches_only_when_it_is_the_same_file() {
+        let (_dir, path) = watched();
+        let other_case = path.with_file_name("Notes.txt");
+        assert!(!is_watched_path(&other_case, &path, false));
+
+        // What a case-insensitive filesystem does: both names open one file.
+        fs::hard_link(&path, &other_case).unwrap();
+        assert!(is_watched_path(&other_case, &path, false));
+
+        fs::remove_file(&other_case).unwrap();
+        fs::write(&other_case, "a different file\n").unwrap();
+        assert!(!is_watched_path(&other_case, &path, false));
+    }
+
+    #[test]
+    fn other_file_in_the_directory_does_not_match() {
+        let (_dir, path) = watched();
+        assert!(!is_watched_path(&path.with_file_name("notes_backup.txt"), &path, false));
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{ConflictStrategy, EXIT_DIFFER};
+use serde_json::json;
+
+// ----------------------
+// PREFLIGHT
+// ----------------------
+
+// --preflight: what the startup reconciliation would do to a pair, worked
+// out from the same rules without writing anything.
+
+// The options that decide the startup reconciliation.
+pub struct Rules {
+    pub overwrite: bool,
+    pub no_seed: bool,
+    pub conflict: ConflictStrategy,
+    // --reconcile-command resolves every conflict.
+    pub reconcile: bool,
+    // --read-only-peer: B is never copied onto A.
+    pub read_only_peer: bool,
+}
+
+pub enum Action {
+    // The files are identical.
+    Nothing,
+    // One side is copied over the other. `conflict` means both had content,
+    // so both would be backed up first.
+    Copy { from: &'static str, to: &'static str, bytes: u64, conflict: bool },
+    // Both have content and --overwrite wasn't given: the run would stop.
+    Blocked,
+    // --conflict keep-both: each file is copied next to the other.
+    KeepBoth { bytes: u64 },
+    // --reconcile-command: both are backed up and replaced by its output.
+    Reconcile,
+    // One side is empty and --no-seed, or a read-only B that would have
+    // seeded A, leaves it that way.
+    Skipped,
+}
+
+impl Rules {
+    pub fn plan(&self, differ: bool, len_a: u64, len_b: u64) -> Action {
+        if !differ {
+            return Action::Nothing;
+        }
+        let copy = |a_wins: bool, conflict: bool| match a_wins {
+            true => Action::Copy { from: "A", to: "B", bytes: len_a, conflict },
+            false if self.read_only_peer => Action::Skipped,
+            false => Action::Copy { from: "B", to: "A", bytes: len_b, conflict },
+        };
+
+        if len_a > 0 && len_b > 0 {
+            if self.reconcile {
+                return Action::Reconcile;
+            }
+            if self.conflict == ConflictStrategy::KeepBoth {
+                return Action::KeepBoth { bytes: len_a + len_b };
+            }
+            if !self.overwrite {
+                return Action::Blocked;
+            }
+            copy(self.conflict.a_wins(len_a, len_b), true)
+        } else if self.no_seed {
+            Action::Skipped
+        } else {
+            copy(len_a > 0, false)
+        }
+    }
+}
+
+impl Action {
+    // Reads as what the run "would" do.
+    pub fn describe(&self, conflict: ConflictStrategy) -> String {
+        match self {
+            Action::Nothing => "do nothing (the files are identical)".to_string(),
+            Action::Copy { from, to, bytes, conflict: true } => {
+                format!("back up both and copy {} -> {} ({} bytes), resolving a conflict by {}", from, to, bytes, conflict)
+            }
+            Action::Copy { from, to, bytes, conflict: false } => format!("seed {} -> {} ({} bytes)", from, to, bytes),
+            Action::KeepBoth { bytes } => format!("keep both, copying each file next to the other ({} bytes)", bytes),
+            Action::Reconcile => "back up both and replace them with what --reconcile-command writes".to_string(),
+            Action::Blocked => "stop, as both files have content and differ (pass --overwrite to sync them)".to_string(),
+            Action::Skipped => "leave the empty file as it is".to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let (action, from, to, bytes, conflict) = match *self {
+            Action::Nothing => ("none", None, None, 0, false),
+            Action::Copy { from, to, bytes, conflict } => ("copy", Some(from), Some(to), bytes, conflict),
+            Action::KeepBoth { bytes } => ("keep_both", None, None, bytes, true),
+            Action::Reconcile => ("reconcile", None, None, 0, true),
+            Action::Blocked => ("blocked", None, None, 0, true),
+            Action::Skipped => ("skip", None, None, 0, false),
+        };
+        json!({ "action": action, "from": from, "to": to, "bytes": bytes, "conflict": conflict })
+    }
+}
+
+// Tallies a set of planned actions, for a batch.
+#[derive(Default)]
+pub struct Totals {
+    pub differ: u64,
+    pub copies: u64,
+    pub bytes: u64,
+    pub conflicts: u64,
+    pub blocked: u64,
+}
+
+impl Totals {
+    pub fn add(&mut self, action: &Action) {
+        match *action {
+            Action::Nothing => return,
+            Action::Copy { bytes, conflict, .. } => {
+                self.copies += 1;
+                self.bytes += bytes;
+                self.conflicts += conflict as u64;
+            }
+            Action::KeepBoth { bytes } => {
+                self.copies += 2;
+                self.bytes += bytes;
+                self.conflicts += 1;
+            }
+            Action::Reconcile => self.conflicts += 1,
+            Action::Blocked => {
+                self.conflicts += 1;
+                self.blocked += 1;
+            }
+            Action::Skipped => {}
+        }
+        self.differ += 1;
+    }
+
+    pub fn print(&self) {
+        log_out!("🧭 Preflight (nothing was written):");
+        log_out!("   Differ:    {}", self.differ);
+        log_out!("   Copies:    {} ({} bytes)", self.copies, self.bytes);
+        log_out!("   Conflicts: {} ({} would stop the run)", self.conflicts, self.blocked);
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "type": "preflight_summary",
+            "differ": self.differ,
+            "copies": self.copies,
+            "bytes": self.bytes,
+            "conflicts": self.conflicts,
+            "blocked": self.blocked,
+        })
+    }
+
+    // A real run exits as it would on the first blocked pair.
+    pub fn exit_code(&self) -> i32 {
+        if self.blocked > 0 { EXIT_DIFFER } else { 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(overwrite: bool, conflict: ConflictStrategy) -> Rules {
+        Rules { overwrite, no_seed: false, conflict, reconcile: false, read_only_peer: false }
+    }
+
+    fn planned(rules: &Rules, len_a: u64, len_b: u64) -> serde_json::Value {
+        rules.plan(true, len_a, len_b).to_json()
+    }
+
+    fn copy(from: &str, to: &str, bytes: u64, conflict: bool) -> serde_json::Value {
+        json!({ "action": "copy", "from": from, "to": to, "bytes": bytes, "conflict": conflict })
+    }
+
+    #[test]
+    fn identical_files_need_nothing() {
+        let rules = rules(true, ConflictStrategy::AWins);
+        assert_eq!(rules.plan(false, 10, 10).to_json()["action"], "none");
+    }
+
+    #[test]
+    fn conflict_without_overwrite_is_blocked() {
+        let rules = rules(false, ConflictStrategy::AWins);
+        assert_eq!(planned(&rules, 10, 20)["action"], "blocked");
+        let mut totals = Totals::default();
+        totals.add(&rules.plan(true, 10, 20));
+        assert_eq!(totals.exit_code(), EXIT_DIFFER);
+    }
+
+    #[test]
+    fn conflict_strategy_picks_the_winner() {
+        assert_eq!(planned(&rules(true, ConflictStrategy::AWins), 10, 20), copy("A", "B", 10, true));
+        assert_eq!(planned(&rules(true, ConflictStrategy::PreferLarger), 10, 20), copy("B", "A", 20, true));
+        assert_eq!(planned(&rules(true, ConflictStrategy::PreferSmaller), 10, 20), copy("A", "B", 10, true));
+        // A wins a tie.
+        assert_eq!(planned(&rules(true, ConflictStrategy::PreferSmaller), 10, 10), copy("A", "B", 10, true));
+    }
+
+    #[test]
+    fn keep_both_and_reconcile_need_no_overwrite() {
+        assert_eq!(planned(&rules(false, ConflictStrategy::KeepBoth), 10, 20)["action"], "keep_both");
+        let reconcile = Rules { reconcile: true, ..rules(false, ConflictStrategy::AWins) };
+        assert_eq!(planned(&reconcile, 10, 20)["action"], "reconcile");
+    }
+
+    #[test]
+    fn empty_side_is_seeded_unless_no_seed() {
+        let rules = rules(false, ConflictStrategy::AWins);
+        assert_eq!(planned(&rules, 10, 0), copy("A", "B", 10, false));
+        assert_eq!(planned(&rules, 0, 20), copy("B", "A", 20, false));
+        let no_seed = Rules { no_seed: true, ..rules };
+        assert_eq!(planned(&no_seed, 10, 0)["action"], "skip");
+    }
+
+    #[test]
+    fn read_only_b_never_seeds_a() {
+        let read_only = Rules { read_only_peer: true, ..rules(false, ConflictStrategy::AWins) };
+        assert_eq!(planned(&read_only, 0, 20)["action"], "skip");
+        assert_eq!(planned(&read_only, 10, 0), copy("A", "B", 10, false));
+    }
+
+    #[test]
+    fn totals_add_up() {
+        let rules = rules(true, ConflictStrategy::KeepBoth);
+        let mut totals = Totals::default();
+        for (len_a, len_b) in [(10, 20), (5, 0), (0, 0)] {
+            totals.add(&rules.plan(true, len_a, len_b));
+        }
+        totals.add(&rules.plan(false, 1, 1));
+        assert_eq!((totals.differ, totals.copies, totals.bytes, totals.conflicts), (3, 4, 35, 1));
+        assert_eq!(totals.exit_code(), 0);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::fileops::write_atomic;
+use crate::hash::{HashAlgo, HashOptions, compute_hash};
+use serde_json::{Value, json};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ----------------------
+// FINAL REPORT
+// ----------------------
+
+// --report-file: one JSON document written when iyr exits, for CI to
+// archive. Kept in a global, like the tee log, so the signal thread and the
+// --fail-fast exit can write it without threading it everywhere.
+static REPORT: Mutex<Option<Report>> = Mutex::new(None);
+
+// Where the report goes. --summary-json-stdout prints it as the only thing
+// on stdout, on one line, so it can be captured with `$(iyr ...)`.
+pub enum Sink {
+    File(PathBuf),
+    Stdout,
+}
+
+struct Report {
+    sink: Sink,
+    started: u64,
+    pair: Option<(PathBuf, PathBuf, HashAlgo)>,
+    syncs: Vec<Value>,
+    conflicts: Vec<Value>,
+    errors: Vec<Value>,
+    touches: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn with_report(f: impl FnOnce(&mut Report)) {
+    if let Some(report) = REPORT.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        f(report);
+    }
+}
+
+pub fn init(sink: Sink) {
+    *REPORT.lock().unwrap_or_else(|e| e.into_inner()) = Some(Report {
+        sink,
+        started: now(),
+        pair: None,
+        syncs: Vec::new(),
+        conflicts: Vec::new(),
+        errors: Vec::new(),
+        touches: 0,
+    });
+}
+
+// The files whose final hashes go in the report.
+pub fn set_pair(path_a: &Path, path_b: &Path, algo: HashAlgo) {
+    with_report(|r| r.pair = Some((path_a.to_path_buf(), path_b.to_path_buf(), algo)));
+}
+
+pub fn record_sync(from: &str, to: &str, bytes: u64) {
+    with_report(|r| r.syncs.push(json!({ "at": now(), "from": from, "to": to, "bytes": bytes })));
+}
+
+pub fn record_conflict() {
+    with_report(|r| r.conflicts.push(json!({ "at": now() })));
+}
+
+pub fn record_touch() {
+    with_report(|r| r.touches += 1);
+}
+
+pub fn record_error(message: &str) {
+    with_report(|r| r.errors.push(json!({ "at": now(), "message": message })));
+}
+
+// Writes the report, at most once per run. No-op without --report-file or
+// --summary-json-stdout.
+pub fn finish(exit_code: i32) {
+    let Some(report) = REPORT.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+        return;
+    };
+
+    let mut in_sync = None;
+    let final_hashes = report.pair.as_ref().map(|(path_a, path_b, algo)| {
+        let options = HashOptions { algo: *algo, range: None, mmap: false, filter: None };
+        let hash = |path: &Path| compute_hash(path, &options).ok().map(|h| format!("{:x}", h));
+        let (hash_a, hash_b) = (hash(path_a), hash(path_b));
+        in_sync = Some(hash_a.is_some() && hash_a == hash_b);
+        json!({
+            "algo": algo.to_string(),
+            "a": { "path": path_a, "hash": hash_a },
+            "b": { "path": path_b, "hash": hash_b },
+        })
+    });
+
+    let document = json!({
+        "type": "report",
+        "schema_version": crate::logging::SCHEMA_VERSION,
+        "started": report.started,
+        "finished": now(),
+        "exit_code": exit_code,
+        "in_sync": in_sync,
+        "syncs": report.syncs,
+        "conflicts": report.conflicts,
+        "errors": report.errors,
+        "touches": report.touches,
+        "final": final_hashes,
+    });
+
+    match &report.sink {
+        Sink::File(path) => {
+            if let Err(e) = write(path, &document) {
+                log_err!("⚠️ Warning: Could not write report to {:?}: {}", crate::logging::shown(path), e);
+            }
+        }
+        Sink::Stdout => {
+            let _ = writeln!(io::stdout().lock(), "{}", document);
+        }
+    }
+}
+
+fn write(path: &Path, document: &Value) -> io::Result<()> {
+    let text = serde_json::to_string_pretty(document).map_err(io::Error::other)?;
+    write_atomic(path, format!("{}\n", text).as_bytes()).map(|_| ())
+}
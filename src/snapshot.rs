@@ -0,0 +1,148 @@
+use crate::fileops::{clone_file, write_atomic};
+use crate::hash::{HashAlgo, HashOptions, compute_hash};
+use crate::validate::special_file_kind;
+use serde_json::{Value, json};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// ----------------------
+// SNAPSHOTS
+// ----------------------
+
+// `iyr snapshot NAME A B` keeps a copy of both files in `<dir>/NAME/`, next
+// to a manifest with their paths, SHA-256, sizes and mtimes:
+//
+//   NAME/manifest.json
+//   NAME/A/<file name>
+//   NAME/B/<file name>
+//
+// `iyr restore-snapshot NAME` checks the copies against the manifest and
+// puts them back over the original paths.
+
+const MANIFEST: &str = "manifest.json";
+
+const SHA256: HashOptions<'static> = HashOptions { algo: HashAlgo::Sha256, range: None, mmap: false, filter: None };
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// A snapshot name becomes a directory name, so it can't point anywhere else.
+fn snapshot_path(dir: &Path, name: &str) -> io::Result<PathBuf> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid snapshot name {:?}", name),
+        ));
+    }
+    Ok(dir.join(name))
+}
+
+// Returns the snapshot's directory. The copies are made in a scratch
+// directory that is only renamed into place once the manifest is written,
+// so a failed snapshot never looks like a complete one.
+pub fn create(dir: &Path, name: &str, path_a: &Path, path_b: &Path) -> io::Result<PathBuf> {
+    let target = snapshot_path(dir, name)?;
+    if target.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("snapshot {:?} already exists in {:?}", name, dir),
+        ));
+    }
+
+    let scratch = dir.join(format!(".{}.iyr-partial", name));
+    let _ = fs::remove_dir_all(&scratch);
+
+    let result = (|| {
+        let mut files = Vec::new();
+        for (side, path) in [("A", path_a), ("B", path_b)] {
+            files.push(copy_in(&scratch, side, path)?);
+        }
+        let manifest = json!({ "name": name, "created": unix_secs(SystemTime::now()), "files": files });
+        let text = serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?;
+        write_atomic(&scratch.join(MANIFEST), format!("{}\n", text).as_bytes())?;
+        fs::rename(&scratch, &target)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&scratch);
+    }
+    result.map(|_| target)
+}
+
+fn copy_in(scratch: &Path, side: &str, path: &Path) -> io::Result<Value> {
+    if let Some(kind) = special_file_kind(path)? {
+        return Err(invalid(format!("{:?} is {}, not a regular file", path, kind)));
+    }
+    let path = fs::canonicalize(path)?;
+    let file_name = path.file_name().ok_or_else(|| invalid(format!("{:?} has no file name", path)))?;
+
+    let copy = Path::new(side).join(file_name);
+    fs::create_dir_all(scratch.join(side))?;
+    let size = clone_file(&path, &scratch.join(&copy))?;
+
+    // Hash the copy, not the original: it's what a restore will put back.
+    let hash = compute_hash(&scratch.join(&copy), &SHA256)?;
+    let mtime = fs::metadata(&path)?.modified().map(unix_secs).ok();
+
+    Ok(json!({
+        "side": side,
+        "path": path,
+        "copy": copy,
+        "sha256": format!("{:x}", hash),
+        "size": size,
+        "mtime": mtime,
+    }))
+}
+
+struct Entry {
+    path: PathBuf,
+    copy: PathBuf,
+    mtime: Option<u64>,
+}
+
+// Returns the restored paths. Every copy is verified before any file is
+// touched, so a damaged snapshot restores nothing.
+pub fn restore(dir: &Path, name: &str) -> io::Result<Vec<PathBuf>> {
+    let root = snapshot_path(dir, name)?;
+    let manifest_path = root.join(MANIFEST);
+    let bytes = fs::read(&manifest_path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(e.kind(), format!("no snapshot {:?} in {:?}", name, dir)),
+        _ => e,
+    })?;
+    let manifest: Value = serde_json::from_slice(&bytes).map_err(|e| invalid(format!("{:?}: {}", manifest_path, e)))?;
+
+    let files = manifest["files"].as_array().ok_or_else(|| invalid(format!("{:?} lists no files", manifest_path)))?;
+    let mut entries = Vec::new();
+    for file in files {
+        let (Some(path), Some(copy), Some(sha256)) = (file["path"].as_str(), file["copy"].as_str(), file["sha256"].as_str())
+        else {
+            return Err(invalid(format!("{:?} has an incomplete file entry", manifest_path)));
+        };
+        let copy = root.join(copy);
+        if !compute_hash(&copy, &SHA256)?.matches_hex(sha256) {
+            return Err(invalid(format!("{:?} doesn't match its checksum in the snapshot", copy)));
+        }
+        entries.push(Entry { path: PathBuf::from(path), copy, mtime: file["mtime"].as_u64() });
+    }
+
+    let mut restored = Vec::new();
+    for entry in entries {
+        clone_file(&entry.copy, &entry.path)?;
+        if let Some(mtime) = entry.mtime {
+            // Best effort: the content is what matters.
+            let _ = File::options()
+                .write(true)
+                .open(&entry.path)
+                .and_then(|f| f.set_modified(UNIX_EPOCH + Duration::from_secs(mtime)));
+        }
+        restored.push(entry.path);
+    }
+    Ok(restored)
+}
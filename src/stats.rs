@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// ----------------------
+// RUN STATISTICS
+// ----------------------
+
+// Counters for a single run. Kept behind atomics so the signal thread can
+// read a snapshot while the event loop keeps updating them.
+pub struct Stats {
+    started: Instant,
+    events: AtomicU64,
+    syncs: AtomicU64,
+    bytes: AtomicU64,
+    conflicts: AtomicU64,
+    // Events that only changed a file's modification time.
+    touches: AtomicU64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats {
+            started: Instant::now(),
+            events: AtomicU64::new(0),
+            syncs: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            conflicts: AtomicU64::new(0),
+            touches: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_events(&self, count: u64) {
+        self.events.fetch_add(count, Ordering::Relaxed);
+    }
+
+    // `from` and `to` name the sides ("A", "B") for --report-file.
+    pub fn record_sync(&self, from: &str, to: &str, bytes: u64) {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        crate::report::record_sync(from, to, bytes);
+    }
+
+    pub fn record_conflict(&self) {
+        self.conflicts.fetch_add(1, Ordering::Relaxed);
+        crate::report::record_conflict();
+    }
+
+    pub fn record_touch(&self) {
+        self.touches.fetch_add(1, Ordering::Relaxed);
+        crate::report::record_touch();
+    }
+
+    pub fn syncs(&self) -> u64 {
+        self.syncs.load(Ordering::Relaxed)
+    }
+
+    pub fn conflicts(&self) -> u64 {
+        self.conflicts.load(Ordering::Relaxed)
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn print_summary(&self) {
+        let uptime = self.uptime().as_secs();
+        log_out!("📈 Stats (uptime {}h {:02}m {:02}s)", uptime / 3600, (uptime / 60) % 60, uptime % 60);
+        log_out!("   Events:    {}", self.events.load(Ordering::Relaxed));
+        log_out!("   Syncs:     {}", self.syncs());
+        log_out!("   Bytes:     {}", self.bytes.load(Ordering::Relaxed));
+        log_out!("   Conflicts: {}", self.conflicts());
+        log_out!("   Touches:   {} (content unchanged)", self.touches.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,311 @@
+use crate::compress;
+use crate::logging::shown;
+use serde_json::{Value, json};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// ----------------------
+// STARTUP VALIDATION
+// ----------------------
+
+// --strict-text: scan whole files for control characters, not just the head.
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_strict_text(enabled: bool) {
+    STRICT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn strict_text() -> bool {
+    STRICT.load(Ordering::Relaxed)
+}
+
+// One named startup check. Humans get the messages printed while it runs;
+// --json consumers get the whole report as a single event.
+pub struct Check {
+    name: &'static str,
+    path: Option<PathBuf>,
+    passed: bool,
+    detail: Option<String>,
+}
+
+#[derive(Default)]
+pub struct ValidationReport {
+    checks: Vec<Check>,
+}
+
+impl ValidationReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let checks: Vec<Value> = self.checks.iter().map(|c| json!({
+            "check": c.name,
+            "path": c.path,
+            "passed": c.passed,
+            "detail": c.detail,
+        })).collect();
+
+        json!({ "type": "validation", "passed": self.passed(), "checks": checks })
+    }
+
+    // Regular file check. Logs why the path was refused.
+    pub fn check_regular_file(&mut self, path: &Path) -> io::Result<bool> {
+        let kind = special_file_kind(path)?;
+        if let Some(kind) = kind {
+            log_err!("❌ Error: {:?} is {}, not a regular file.", shown(path), kind);
+            log_err!("This tool only syncs regular files.");
+        }
+        Ok(self.push(Check {
+            name: "regular_file",
+            path: Some(path.to_path_buf()),
+            passed: kind.is_none(),
+            detail: kind.map(str::to_string),
+        }))
+    }
+
+    pub fn check_names(&mut self, path_a: &Path, path_b: &Path) -> bool {
+        let passed = compare_stem_ext(path_a, path_b);
+        self.push(Check { name: "name_match", path: None, passed, detail: None })
+    }
+
+    // Text content check. Logs what was found instead of text.
+    pub fn check_text(&mut self, path: &Path) -> io::Result<bool> {
+        let found = is_valid_text_file(path)?;
+        match &found {
+            TextCheck::Binary { kind } => log_err!("❌ Error: {:?} is not UTF-8 text (detected {}).", shown(path), kind),
+            TextCheck::NotAFile => log_err!("❌ Error: {:?} is not a file.", shown(path)),
+            TextCheck::Control { offset, ch } => {
+                log_err!("❌ Error: {:?} contains control character {:?} at byte {} (--strict-text).", shown(path), ch, offset)
+            }
+            TextCheck::Text { .. } | TextCheck::Empty => {}
+        }
+        Ok(self.push(Check {
+            name: "text_content",
+            path: Some(path.to_path_buf()),
+            passed: found.is_text(),
+            detail: Some(found.detail()),
+        }))
+    }
+
+    // One error event per failed check, for --json consumers.
+    pub fn emit_errors(&self) {
+        for check in self.checks.iter().filter(|c| !c.passed) {
+            let message = match &check.detail {
+                Some(detail) => format!("{} check failed: {}", check.name, detail),
+                None => format!("{} check failed", check.name),
+            };
+            crate::logging::emit_error("validation", &message, check.path.as_deref());
+        }
+    }
+
+    fn push(&mut self, check: Check) -> bool {
+        let passed = check.passed;
+        self.checks.push(check);
+        passed
+    }
+}
+
+// PHASE 0: Regular File Check
+// Reading a FIFO or device blocks or never ends, so anything that isn't a
+// plain file is refused before we try to hash it. Returns what the path is
+// when it can't be synced.
+pub fn special_file_kind(path: &Path) -> io::Result<Option<&'static str>> {
+    let file_type = fs::metadata(path)?.file_type();
+
+    if file_type.is_file() {
+        return Ok(None);
+    }
+    if file_type.is_dir() {
+        return Ok(Some("a directory"));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return Ok(Some("a named pipe (FIFO)"));
+        }
+        if file_type.is_socket() {
+            return Ok(Some("a socket"));
+        }
+        if file_type.is_block_device() {
+            return Ok(Some("a block device"));
+        }
+        if file_type.is_char_device() {
+            return Ok(Some("a character device"));
+        }
+    }
+
+    Ok(Some("a special file"))
+}
+
+// PHASE 1: Name & Extension Check
+fn compare_stem_ext(path_a: &Path, path_b: &Path) -> bool {
+    let name_a = path_a.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let name_b = path_b.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let name_b = compress::plain_name(path_b, name_b);
+
+    // 1. Check for empty filenames
+    if name_a.is_empty() || name_b.is_empty() {
+        log_err!("❌ Error: Invalid file paths provided.");
+        return false;
+    }
+
+    // 2. Strict Name Equality (Case Insensitive)
+    if !name_a.eq_ignore_ascii_case(name_b) {
+        log_err!("❌ Error: Files must have the same name and extension.");
+        log_err!("   File A: {}", name_a);
+        log_err!("   File B: {}", name_b);
+        return false;
+    }
+    
+    // 3. Extension Check
+    if path_a.extension().is_none() || path_b.extension().is_none() {
+         log_err!("⚠️ Warning: One or both files have no extension.");
+         // We allow this, but warn.
+    }
+
+    true
+}
+
+// PHASE 2: Magic Number & Content Check
+// What the content check found at the start of a file.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TextCheck {
+    Text { encoding: String },
+    // `kind` is the detected MIME type, or the encoding for text that
+    // isn't UTF-8.
+    Binary { kind: String },
+    Empty,
+    NotAFile,
+    // --strict-text found a NUL or other control character further in.
+    Control { offset: usize, ch: char },
+}
+
+impl TextCheck {
+    // Empty files count as text: there is nothing in them to corrupt.
+    pub fn is_text(&self) -> bool {
+        matches!(self, TextCheck::Text { .. } | TextCheck::Empty)
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            TextCheck::Text { encoding } => encoding.clone(),
+            TextCheck::Binary { kind } => kind.clone(),
+            TextCheck::Empty => "empty".to_string(),
+            TextCheck::NotAFile => "not a file".to_string(),
+            TextCheck::Control { offset, ch } => format!("control character U+{:04X} at byte {}", *ch as u32, offset),
+        }
+    }
+}
+
+pub fn is_valid_text_file(path: &Path) -> io::Result<TextCheck> {
+    if !path.is_file() {
+        return Ok(TextCheck::NotAFile);
+    }
+
+    let mut file = compress::open(path)?;
+    let mut buffer = [0u8; 1024];
+    let bytes_read = file.read(&mut buffer)?;
+
+    if bytes_read == 0 {
+        return Ok(TextCheck::Empty);
+    }
+
+    let head = &buffer[..bytes_read];
+
+    if let Some(kind) = infer::get(head) {
+        return Ok(TextCheck::Binary { kind: kind.mime_type().to_string() });
+    }
+
+    let content_type = content_inspector::inspect(head);
+    match content_type {
+        content_inspector::ContentType::UTF_8 |
+        content_inspector::ContentType::UTF_8_BOM => {}
+        _ => return Ok(TextCheck::Binary { kind: content_type.to_string() }),
+    }
+
+    if strict_text() {
+        // The head only vouches for the first 1024 bytes.
+        let bytes = compress::read(path)?;
+        let text = match std::str::from_utf8(&bytes) {
+            Ok(text) => text,
+            Err(e) => return Ok(TextCheck::Binary { kind: format!("non-UTF-8 data at byte {}", e.valid_up_to()) }),
+        };
+        if let Some((offset, ch)) = find_control_char(text) {
+            return Ok(TextCheck::Control { offset, ch });
+        }
+    }
+
+    Ok(TextCheck::Text { encoding: content_type.to_string() })
+}
+
+// First NUL or other non-printable control character, with its byte offset.
+// Tabs and line endings are fine. Only used with --strict-text.
+pub fn find_control_char(text: &str) -> Option<(usize, char)> {
+    text.char_indices().find(|(_, ch)| ch.is_control() && !matches!(ch, '\t' | '\n' | '\r'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use content_inspector::ContentType;
+
+    fn check(content: &[u8]) -> TextCheck {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sample.txt");
+        fs::write(&path, content).unwrap();
+        is_valid_text_file(&path).unwrap()
+    }
+
+    #[test]
+    fn utf8_is_text() {
+        assert_eq!(check("plain text, ünïcödé\n".as_bytes()), TextCheck::Text { encoding: ContentType::UTF_8.to_string() });
+    }
+
+    #[test]
+    fn utf8_with_bom_is_text() {
+        assert_eq!(check(b"\xef\xbb\xbfwith a BOM\n"), TextCheck::Text { encoding: ContentType::UTF_8_BOM.to_string() });
+    }
+
+    #[test]
+    fn empty_file_counts_as_text() {
+        let result = check(b"");
+        assert_eq!(result, TextCheck::Empty);
+        assert!(result.is_text());
+    }
+
+    #[test]
+    fn image_is_binary_by_its_mime_type() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0\x1f\x15\xc4\x89";
+        assert_eq!(check(png), TextCheck::Binary { kind: "image/png".to_string() });
+    }
+
+    #[test]
+    fn nul_bytes_are_binary() {
+        let result = check(b"abc\0\0\0def");
+        assert_eq!(result, TextCheck::Binary { kind: ContentType::BINARY.to_string() });
+        assert!(!result.is_text());
+    }
+
+    #[test]
+    fn utf16_is_refused_by_its_encoding() {
+        assert_eq!(check(b"\xff\xfeh\0i\0\n\0"), TextCheck::Binary { kind: ContentType::UTF_16LE.to_string() });
+    }
+
+    #[test]
+    fn directory_is_not_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(is_valid_text_file(dir.path()).unwrap(), TextCheck::NotAFile);
+    }
+
+    #[test]
+    fn control_characters_other_than_whitespace_are_found() {
+        assert_eq!(find_control_char("tabs\tand\r\nnewlines\n"), None);
+        assert_eq!(find_control_char("ab\u{1b}[0m"), Some((2, '\u{1b}')));
+        assert_eq!(find_control_char("é\0"), Some((2, '\0')));
+    }
+}
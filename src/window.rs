@@ -0,0 +1,117 @@
+use chrono::{NaiveTime, Timelike};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// ----------------------
+// SYNC WINDOW
+// ----------------------
+
+const DAY: u32 = 24 * 60 * 60;
+
+// --sync-window: the time of day, in local time, during which syncs are
+// applied, e.g. `09:00-17:00`. A window can wrap past midnight
+// (`22:00-06:00`). The start is inclusive and the end exclusive.
+#[derive(Clone, Copy, Debug)]
+pub struct SyncWindow {
+    // Seconds since midnight.
+    start: u32,
+    end: u32,
+}
+
+impl SyncWindow {
+    pub fn is_open(&self, now: NaiveTime) -> bool {
+        let now = now.num_seconds_from_midnight();
+        if self.start <= self.end {
+            (self.start..self.end).contains(&now)
+        } else {
+            now >= self.start || now < self.end
+        }
+    }
+
+    // How long until the window next opens; zero while it's open.
+    pub fn until_open(&self, now: NaiveTime) -> Duration {
+        if self.is_open(now) {
+            return Duration::ZERO;
+        }
+        let now = now.num_seconds_from_midnight();
+        Duration::from_secs(((self.start + DAY - now) % DAY) as u64)
+    }
+}
+
+impl fmt::Display for SyncWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hm = |secs: u32| (secs / 3600, secs / 60 % 60);
+        let ((sh, sm), (eh, em)) = (hm(self.start), hm(self.end));
+        write!(f, "{:02}:{:02}-{:02}:{:02}", sh, sm, eh, em)
+    }
+}
+
+impl FromStr for SyncWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-').ok_or("expected HH:MM-HH:MM, e.g. 09:00-17:00")?;
+        let parse = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                .map(|t| t.num_seconds_from_midnight())
+                .map_err(|_| format!("invalid time '{}', expected HH:MM", t.trim()))
+        };
+
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start == end {
+            return Err("sync window is empty: start and end are the same".to_string());
+        }
+        Ok(SyncWindow { start, end })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        let window: SyncWindow = "9:05 - 17:30".parse().unwrap();
+        assert_eq!(window.to_string(), "09:05-17:30");
+    }
+
+    #[test]
+    fn rejects_malformed_and_empty_windows() {
+        for bad in ["09:00", "09:00-25:00", "nine-five", "12:00-12:00"] {
+            assert!(bad.parse::<SyncWindow>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn start_is_inclusive_and_end_exclusive() {
+        let window: SyncWindow = "09:00-17:00".parse().unwrap();
+        assert!(!window.is_open(at(8, 59)));
+        assert!(window.is_open(at(9, 0)));
+        assert!(window.is_open(at(16, 59)));
+        assert!(!window.is_open(at(17, 0)));
+    }
+
+    #[test]
+    fn wraps_past_midnight() {
+        let window: SyncWindow = "22:00-06:00".parse().unwrap();
+        assert!(window.is_open(at(23, 30)));
+        assert!(window.is_open(at(0, 0)));
+        assert!(window.is_open(at(5, 59)));
+        assert!(!window.is_open(at(6, 0)));
+        assert!(!window.is_open(at(12, 0)));
+    }
+
+    #[test]
+    fn until_open_counts_to_the_next_start() {
+        let window: SyncWindow = "09:00-17:00".parse().unwrap();
+        assert_eq!(window.until_open(at(10, 0)), Duration::ZERO);
+        assert_eq!(window.until_open(at(8, 30)), Duration::from_secs(30 * 60));
+        // Closed for the day: it opens tomorrow morning.
+        assert_eq!(window.until_open(at(18, 0)), Duration::from_secs(15 * 3600));
+    }
+}
@@ -0,0 +1,201 @@
+// Startup behaviour: --verify, the startup checks and the startup sync.
+// Runs that would go on watching are stopped once they get there.
+
+mod common;
+
+use common::{Pair, Watcher, code, run};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fs;
+use std::io::Read;
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0\x1f\x15\xc4\x89";
+
+#[test]
+fn verify_exits_0_for_identical_files() {
+    let pair = Pair::new("same\n", "same\n");
+    let output = run([pair.a.as_os_str(), pair.b.as_os_str(), "--verify".as_ref()]);
+    assert_eq!(code(&output), 0);
+}
+
+#[test]
+fn verify_exits_1_for_different_files() {
+    let pair = Pair::new("one\n", "two\n");
+    let output = run([pair.a.as_os_str(), pair.b.as_os_str(), "--verify".as_ref()]);
+    assert_eq!(code(&output), 1);
+}
+
+#[test]
+fn differing_files_without_overwrite_exit_1() {
+    let pair = Pair::new("one\n", "two\n");
+    let output = run([&pair.a, &pair.b]);
+    assert_eq!(code(&output), 1);
+    assert_eq!(pair.read_a(), "one\n");
+    assert_eq!(pair.read_b(), "two\n");
+}
+
+#[test]
+fn missing_path_exits_2() {
+    let pair = Pair::new("one\n", "one\n");
+    let output = run([pair.a.as_os_str(), pair.dir.path().join("nope.txt").as_os_str()]);
+    assert_eq!(code(&output), 2);
+}
+
+#[test]
+fn bad_argument_exits_2() {
+    let pair = Pair::new("one\n", "one\n");
+    let output = run([pair.a.as_os_str(), pair.b.as_os_str(), "--no-such-option".as_ref()]);
+    assert_eq!(code(&output), 2);
+}
+
+#[test]
+fn missing_relative_display_base_exits_2() {
+    let pair = Pair::new("one\n", "one\n");
+    let base = pair.dir.path().join("nonexistent");
+    let mut flag = std::ffi::OsString::from("--relative-display=");
+    flag.push(&base);
+    let output = run([pair.a.as_os_str(), pair.b.as_os_str(), "--verify".as_ref(), &flag]);
+    assert_eq!(code(&output), 2);
+}
+
+#[test]
+fn binary_content_exits_3() {
+    let pair = Pair::with_bytes("notes.txt", b"text\n", PNG);
+    let output = run([&pair.a, &pair.b]);
+    assert_eq!(code(&output), 3);
+}
+
+#[test]
+fn name_mismatch_exits_3() {
+    let pair = Pair::new("one\n", "one\n");
+    let other = pair.b.with_file_name("other.txt");
+    fs::rename(&pair.b, &other).unwrap();
+    let output = run([&pair.a, &other]);
+    assert_eq!(code(&output), 3);
+}
+
+#[test]
+fn failed_startup_write_exits_4() {
+    let pair = Pair::new("content\n", "");
+    common::block_writes(&pair.b);
+    let output = run([&pair.a, &pair.b]);
+    assert_eq!(code(&output), 4);
+    assert_eq!(pair.read_b(), "");
+}
+
+#[test]
+fn verify_leaves_both_files_untouched() {
+    let pair = Pair::new("one\n", "two\n");
+    let mtimes = || [&pair.a, &pair.b].map(|p| fs::metadata(p).unwrap().modified().unwrap());
+    let (before, listing) = (mtimes(), pair.listing());
+    // Any write would now leave a later modification time.
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    for args in [&["--verify"][..], &["--verify", "--hash-algo", "sha256"], &["--verify", "--json"]] {
+        let output = run([pair.a.as_os_str(), pair.b.as_os_str()].into_iter().chain(args.iter().map(|a| a.as_ref())));
+        assert_eq!(code(&output), 1);
+    }
+    assert_eq!(mtimes(), before);
+    assert_eq!(pair.listing(), listing);
+    assert_eq!(pair.read_a(), "one\n");
+    assert_eq!(pair.read_b(), "two\n");
+}
+
+#[test]
+fn empty_b_is_seeded_from_a() {
+    let pair = Pair::new("seed\n", "");
+    let _iyr = Watcher::start([&pair.a, &pair.b]);
+    assert_eq!(pair.read_b(), "seed\n");
+    // Nothing could be lost, so nothing is backed up.
+    assert_eq!(pair.listing(), ["notes.txt", "notes.txt"]);
+}
+
+#[test]
+fn empty_a_is_seeded_from_b() {
+    let pair = Pair::new("", "seed\n");
+    let _iyr = Watcher::start([&pair.a, &pair.b]);
+    assert_eq!(pair.read_a(), "seed\n");
+    assert_eq!(pair.listing(), ["notes.txt", "notes.txt"]);
+}
+
+#[test]
+fn no_seed_leaves_the_empty_file() {
+    let pair = Pair::new("seed\n", "");
+    let _iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--no-seed".as_ref()]);
+    assert_eq!(pair.read_b(), "");
+}
+
+#[test]
+fn read_only_peer_never_seeds_a() {
+    let pair = Pair::new("", "seed\n");
+    let _iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--read-only-peer".as_ref(), "alert".as_ref()]);
+    assert_eq!(pair.read_a(), "");
+    assert_eq!(pair.read_b(), "seed\n");
+}
+
+#[test]
+fn empty_compressed_b_is_seeded() {
+    let pair = Pair::new("plain\n", "");
+    let b = pair.b.with_file_name("notes.txt.gz");
+    // Empty content still takes a gzip header and trailer on disk.
+    GzEncoder::new(fs::File::create(&b).unwrap(), Compression::default()).finish().unwrap();
+    fs::remove_file(&pair.b).unwrap();
+
+    let _iyr = Watcher::start([pair.a.as_os_str(), b.as_os_str(), "--decompress-b".as_ref(), "gzip".as_ref()]);
+    let mut plain = String::new();
+    GzDecoder::new(fs::File::open(&b).unwrap()).read_to_string(&mut plain).unwrap();
+    assert_eq!(plain, "plain\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn reconcile_command_replaces_both_with_its_output() {
+    let pair = Pair::new("from A\n", "from B\n");
+    let _iyr = Watcher::start([
+        pair.a.as_os_str(),
+        pair.b.as_os_str(),
+        "--reconcile-command".as_ref(),
+        "sh -c 'cat {a} {b} > {out}'".as_ref(),
+    ]);
+    assert_eq!(pair.read_a(), "from A\nfrom B\n");
+    assert_eq!(pair.read_b(), "from A\nfrom B\n");
+    assert_eq!(fs::read_to_string(pair.b.with_file_name("notes_backup.txt")).unwrap(), "from B\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_reconcile_command_leaves_both_files() {
+    let pair = Pair::new("from A\n", "from B\n");
+    let output = run([pair.a.as_os_str(), pair.b.as_os_str(), "--reconcile-command".as_ref(), "false".as_ref()]);
+    assert_eq!(code(&output), 1);
+    assert_eq!(pair.read_a(), "from A\n");
+    assert_eq!(pair.read_b(), "from B\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn hook_output_stays_out_of_the_json_summary() {
+    use std::process::{Command, Stdio};
+
+    let pair = Pair::new("same\n", "same\n");
+    let (stdout, stderr) = (pair.dir.path().join("stdout"), pair.dir.path().join("stderr"));
+    let mut child = Command::new(common::BIN)
+        .args([pair.a.as_os_str(), pair.b.as_os_str()])
+        .args(["--summary-json-stdout", "--on-start-command", "echo from the hook", "--on-stop-command", "echo from the hook"])
+        .stdin(Stdio::null())
+        .stdout(fs::File::create(&stdout).unwrap())
+        .stderr(fs::File::create(&stderr).unwrap())
+        .spawn()
+        .unwrap();
+
+    let hooked = common::wait_until(common::PATIENCE, || fs::read_to_string(&stderr).unwrap().contains("from the hook"));
+    Command::new("kill").arg("-TERM").arg(child.id().to_string()).status().unwrap();
+    assert!(child.wait().unwrap().success());
+    assert!(hooked, "the start hook never ran");
+
+    let printed = fs::read_to_string(&stdout).unwrap();
+    assert_eq!(printed.lines().count(), 1, "{}", printed);
+    assert!(serde_json::from_str::<serde_json::Value>(&printed).is_ok(), "{}", printed);
+    assert_eq!(fs::read_to_string(&stderr).unwrap().matches("from the hook").count(), 2);
+}
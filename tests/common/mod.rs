@@ -0,0 +1,174 @@
+// Helpers shared by the integration tests. Each test file uses a different
+// subset, hence the allow.
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+pub const BIN: &str = env!("CARGO_BIN_EXE_iyr");
+
+// Short enough to keep the watcher tests quick, long enough for a batch to
+// hold several writes made one after another.
+pub const DEBOUNCE_MS: u64 = 100;
+
+// Files with the same name in two sibling directories, `x/` and `y/`, the
+// way iyr is usually pointed at them. Removed with the temp dir.
+pub struct Pair {
+    pub dir: TempDir,
+    pub a: PathBuf,
+    pub b: PathBuf,
+}
+
+impl Pair {
+    pub fn new(a: &str, b: &str) -> Pair {
+        Pair::with_bytes("notes.txt", a.as_bytes(), b.as_bytes())
+    }
+
+    pub fn with_bytes(name: &str, a: &[u8], b: &[u8]) -> Pair {
+        let dir = tempfile::tempdir().unwrap();
+        let (a_path, b_path) = (dir.path().join("x").join(name), dir.path().join("y").join(name));
+        for (path, content) in [(&a_path, a), (&b_path, b)] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        Pair { dir, a: a_path, b: b_path }
+    }
+
+    pub fn read_a(&self) -> String {
+        fs::read_to_string(&self.a).unwrap()
+    }
+
+    pub fn read_b(&self) -> String {
+        fs::read_to_string(&self.b).unwrap()
+    }
+
+    // Every file next to A and B, by name, to spot files left behind.
+    pub fn listing(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for dir in [self.a.parent().unwrap(), self.b.parent().unwrap()] {
+            for entry in fs::read_dir(dir).unwrap() {
+                names.push(entry.unwrap().file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        names
+    }
+}
+
+// Runs iyr to completion, with nothing on stdin so it never waits on a
+// prompt.
+pub fn run<I, S>(args: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    Command::new(BIN).args(args).stdin(Stdio::null()).output().unwrap()
+}
+
+pub fn code(output: &Output) -> i32 {
+    output.status.code().expect("iyr was killed by a signal")
+}
+
+// Polls `done` until it holds or `timeout` passes. Returns whether it held.
+pub fn wait_until(timeout: Duration, mut done: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if done() {
+            return true;
+        }
+        sleep(Duration::from_millis(20));
+    }
+    done()
+}
+
+// How long a watcher test waits for something that should happen. Even a
+// slow CI machine syncs well within this.
+pub const PATIENCE: Duration = Duration::from_secs(10);
+
+// Long enough for a change that isn't going to be synced to have been, if
+// it were: a few debounce windows.
+pub fn settle() {
+    sleep(Duration::from_millis(DEBOUNCE_MS * 8));
+}
+
+// A running `iyr` watcher. Its output goes to a log file outside the
+// watched directories. Killed when dropped.
+pub struct Watcher {
+    child: Child,
+    _log_dir: TempDir,
+    log: PathBuf,
+}
+
+impl Watcher {
+    // Starts iyr with `--debounce-ms DEBOUNCE_MS` and `args`, and waits for
+    // it to be watching.
+    pub fn start<I, S>(args: I) -> Watcher
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Watcher::start_with_debounce(DEBOUNCE_MS, args)
+    }
+
+    pub fn start_with_debounce<I, S>(debounce_ms: u64, args: I) -> Watcher
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let log_dir = tempfile::tempdir().unwrap();
+        let log = log_dir.path().join("iyr.log");
+        let file = File::create(&log).unwrap();
+        let child = Command::new(BIN)
+            .arg("--debounce-ms")
+            .arg(debounce_ms.to_string())
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(file.try_clone().unwrap())
+            .stderr(file)
+            .spawn()
+            .unwrap();
+        let mut watcher = Watcher { child, _log_dir: log_dir, log };
+
+        let started = wait_until(PATIENCE, || watcher.log().contains("Starting watcher") || !watcher.is_running());
+        assert!(started && watcher.is_running(), "iyr didn't start watching:\n{}", watcher.log());
+        // The paths are registered right after that line.
+        sleep(Duration::from_millis(200));
+        watcher
+    }
+
+    pub fn log(&self) -> String {
+        fs::read_to_string(&self.log).unwrap_or_default()
+    }
+
+    pub fn wait_for_log(&self, needle: &str) {
+        assert!(wait_until(PATIENCE, || self.log().contains(needle)), "never logged {:?}:\n{}", needle, self.log());
+    }
+
+    pub fn is_running(&mut self) -> bool {
+        self.child.try_wait().unwrap().is_none()
+    }
+
+    pub fn wait_exit(&mut self) -> Option<ExitStatus> {
+        wait_until(PATIENCE, || !self.is_running());
+        self.child.try_wait().unwrap()
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Makes the next write iyr tries at `path` fail, even for root: its temp
+// file's name is taken by a directory.
+pub fn block_writes(path: &Path) {
+    let name = path.file_name().unwrap().to_string_lossy();
+    fs::create_dir(path.with_file_name(format!(".{}.iyr-tmp", name))).unwrap();
+}
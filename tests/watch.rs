@@ -0,0 +1,331 @@
+// The event loop, driven through a running watcher: each test edits the
+// files the way another program would and checks what iyr made of it.
+
+mod common;
+
+use common::{PATIENCE, Pair, Watcher, settle, wait_until};
+use std::fs;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+#[test]
+fn deleting_a_file_restores_it_from_its_peer() {
+    let pair = Pair::new("kept\n", "kept\n");
+    let _iyr = Watcher::start([&pair.a, &pair.b]);
+
+    fs::remove_file(&pair.a).unwrap();
+    assert!(wait_until(PATIENCE, || fs::read_to_string(&pair.a).is_ok_and(|a| a == "kept\n")));
+    assert_eq!(pair.read_b(), "kept\n");
+}
+
+#[test]
+fn mirror_deletes_deletes_the_peer() {
+    let pair = Pair::new("gone\n", "gone\n");
+    let _iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--mirror-deletes".as_ref(), "--force".as_ref()]);
+
+    fs::remove_file(&pair.a).unwrap();
+    assert!(wait_until(PATIENCE, || !pair.b.exists()));
+    settle();
+    assert!(!pair.a.exists());
+}
+
+#[test]
+fn mirror_deletes_needs_force() {
+    let pair = Pair::new("kept\n", "kept\n");
+    let output = common::run([pair.a.as_os_str(), pair.b.as_os_str(), "--mirror-deletes".as_ref()]);
+    assert_eq!(common::code(&output), 2);
+}
+
+#[test]
+fn modify_then_delete_in_one_batch_acts_on_the_deletion() {
+    let pair = Pair::new("original\n", "original\n");
+    let iyr = Watcher::start([&pair.a, &pair.b]);
+
+    // Both well within one debounce window, so they arrive as one batch.
+    fs::write(&pair.a, "draft\n").unwrap();
+    fs::remove_file(&pair.a).unwrap();
+
+    assert!(wait_until(PATIENCE, || fs::read_to_string(&pair.a).is_ok_and(|a| a == "original\n")));
+    assert_eq!(pair.read_b(), "original\n");
+    assert!(!iyr.log().contains("Syncing to B"), "the draft was synced:\n{}", iyr.log());
+}
+
+#[test]
+fn truncate_then_rewrite_never_empties_the_peer() {
+    let pair = Pair::new("old content\n", "old content\n");
+    let _iyr = Watcher::start_with_debounce(400, [&pair.a, &pair.b]);
+
+    // The rewrite lands after the truncation's batch was handled, while the
+    // empty file is still settling.
+    fs::File::create(&pair.a).unwrap();
+    let rewrite_at = Instant::now() + Duration::from_millis(600);
+    while Instant::now() < rewrite_at {
+        assert_eq!(pair.read_b(), "old content\n");
+        sleep(Duration::from_millis(10));
+    }
+    fs::write(&pair.a, "new content\n").unwrap();
+
+    assert!(wait_until(PATIENCE, || {
+        let b = pair.read_b();
+        assert!(!b.is_empty(), "B was emptied");
+        b == "new content\n"
+    }));
+}
+
+#[test]
+fn emptied_file_syncs_as_content_by_default() {
+    let pair = Pair::new("text\n", "text\n");
+    let _iyr = Watcher::start([&pair.a, &pair.b]);
+
+    fs::File::create(&pair.a).unwrap();
+    assert!(wait_until(PATIENCE, || pair.read_b().is_empty()));
+}
+
+#[test]
+fn empty_means_ignore_leaves_the_peer() {
+    let pair = Pair::new("text\n", "text\n");
+    let iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--empty-means".as_ref(), "ignore".as_ref()]);
+
+    fs::File::create(&pair.a).unwrap();
+    iyr.wait_for_log("--empty-means ignore");
+    settle();
+    assert_eq!(pair.read_b(), "text\n");
+    assert_eq!(pair.read_a(), "");
+}
+
+#[test]
+fn empty_means_delete_deletes_the_peer() {
+    let pair = Pair::new("text\n", "text\n");
+    let _iyr = Watcher::start([
+        pair.a.as_os_str(),
+        pair.b.as_os_str(),
+        "--empty-means".as_ref(),
+        "delete".as_ref(),
+        "--force".as_ref(),
+    ]);
+
+    fs::File::create(&pair.a).unwrap();
+    assert!(wait_until(PATIENCE, || !pair.b.exists()));
+    settle();
+    assert!(!pair.b.exists(), "B was restored after being deleted");
+    assert_eq!(pair.read_a(), "");
+}
+
+#[test]
+fn empty_means_delete_needs_force() {
+    let pair = Pair::new("text\n", "text\n");
+    let output = common::run([pair.a.as_os_str(), pair.b.as_os_str(), "--empty-means".as_ref(), "delete".as_ref()]);
+    assert_eq!(common::code(&output), 2);
+}
+
+#[test]
+fn bom_is_synced_as_it_is() {
+    let pair = Pair::new("start\n", "start\n");
+    let _iyr = Watcher::start([&pair.a, &pair.b]);
+
+    let with_bom = [BOM, b"with a BOM\n"].concat();
+    fs::write(&pair.a, &with_bom).unwrap();
+    assert!(wait_until(PATIENCE, || fs::read(&pair.b).unwrap() == with_bom));
+
+    fs::write(&pair.a, b"without one\n").unwrap();
+    assert!(wait_until(PATIENCE, || fs::read(&pair.b).unwrap() == b"without one\n"));
+}
+
+#[test]
+fn strip_bom_normalizes_both_files() {
+    let pair = Pair::new("start\n", "start\n");
+    let _iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--strip-bom".as_ref()]);
+
+    fs::write(&pair.a, [BOM, b"edited\n"].concat()).unwrap();
+    assert!(wait_until(PATIENCE, || fs::read(&pair.b).unwrap() == b"edited\n"));
+    assert!(wait_until(PATIENCE, || fs::read(&pair.a).unwrap() == b"edited\n"));
+}
+
+#[test]
+fn failed_bom_write_back_leaves_both_files() {
+    let pair = Pair::new("start\n", "start\n");
+    common::block_writes(&pair.a);
+    let iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--strip-bom".as_ref()]);
+
+    let edited = [BOM, b"edited\n"].concat();
+    fs::write(&pair.a, &edited).unwrap();
+    iyr.wait_for_log("Error writing B");
+    // B would have been stripped while A kept its BOM.
+    assert_eq!(pair.read_b(), "start\n");
+    assert_eq!(fs::read(&pair.a).unwrap(), edited);
+}
+
+#[test]
+fn add_bom_normalizes_both_files() {
+    let pair = Pair::new("start\n", "start\n");
+    let _iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--add-bom".as_ref()]);
+
+    fs::write(&pair.a, b"edited\n").unwrap();
+    let with_bom = [BOM, b"edited\n"].concat();
+    assert!(wait_until(PATIENCE, || fs::read(&pair.b).unwrap() == with_bom));
+    assert!(wait_until(PATIENCE, || fs::read(&pair.a).unwrap() == with_bom));
+}
+
+#[test]
+fn strip_bom_applies_to_the_startup_seed() {
+    let pair = Pair::with_bytes("notes.txt", &[BOM, b"seeded\n"].concat(), b"");
+    let _iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--strip-bom".as_ref()]);
+
+    assert_eq!(fs::read(&pair.b).unwrap(), b"seeded\n");
+    assert_eq!(fs::read(&pair.a).unwrap(), b"seeded\n");
+}
+
+#[test]
+fn same_directory_pair_ignores_its_backups() {
+    // Two files in one directory, told apart only by case where case matters.
+    let dir = tempfile::tempdir().unwrap();
+    let (a, b) = (dir.path().join("notes.txt"), dir.path().join("NOTES.txt"));
+    fs::write(&a, "from A\n").unwrap();
+    fs::write(&b, "from B\n").unwrap();
+    let iyr = Watcher::start([a.as_os_str(), b.as_os_str(), "--overwrite".as_ref()]);
+
+    let (backup_a, backup_b) = (dir.path().join("notes_backup.txt"), dir.path().join("NOTES_backup.txt"));
+    assert_eq!(fs::read_to_string(&backup_a).unwrap(), "from A\n");
+    assert_eq!(fs::read_to_string(&backup_b).unwrap(), "from B\n");
+    assert_eq!(fs::read_to_string(&b).unwrap(), "from A\n");
+
+    // Neither backup is mistaken for a watched file.
+    fs::write(&backup_a, "edited backup\n").unwrap();
+    fs::write(&backup_b, "edited backup\n").unwrap();
+    settle();
+    assert_eq!(fs::read_to_string(&a).unwrap(), "from A\n");
+    assert_eq!(fs::read_to_string(&b).unwrap(), "from A\n");
+
+    fs::write(&a, "synced\n").unwrap();
+    assert!(wait_until(PATIENCE, || fs::read_to_string(&b).unwrap() == "synced\n"));
+    assert_eq!(fs::read_to_string(&backup_a).unwrap(), "edited backup\n");
+    assert!(!iyr.log().contains("File B changed"), "B's own write was synced back:\n{}", iyr.log());
+}
+
+#[test]
+fn same_directory_pair_edited_together_keeps_the_loser_as_a_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let (a, b) = (dir.path().join("notes.txt"), dir.path().join("NOTES.txt"));
+    fs::write(&a, "same\n").unwrap();
+    fs::write(&b, "same\n").unwrap();
+    let _iyr = Watcher::start([&a, &b]);
+
+    // Saved together, as one batch. A wins by default.
+    fs::write(&a, "A's edit\n").unwrap();
+    fs::write(&b, "B's edit\n").unwrap();
+
+    let backup_b = dir.path().join("NOTES_backup.txt");
+    assert!(wait_until(PATIENCE, || fs::read_to_string(&b).unwrap() == "A's edit\n"));
+    assert_eq!(fs::read_to_string(&backup_b).unwrap(), "B's edit\n");
+    settle();
+    assert_eq!(fs::read_to_string(&a).unwrap(), "A's edit\n");
+    assert!(!dir.path().join("notes_backup.txt").exists());
+}
+
+// B as a symlink to a file of the same name in `z/`. Returns the target.
+#[cfg(unix)]
+fn link_b(pair: &Pair) -> std::path::PathBuf {
+    let target = pair.dir.path().join("z").join("notes.txt");
+    fs::create_dir(target.parent().unwrap()).unwrap();
+    fs::rename(&pair.b, &target).unwrap();
+    std::os::unix::fs::symlink(&target, &pair.b).unwrap();
+    target
+}
+
+#[cfg(unix)]
+#[test]
+fn dereference_on_write_true_writes_through_the_link() {
+    let pair = Pair::new("linked\n", "linked\n");
+    let target = link_b(&pair);
+    let _iyr = Watcher::start([&pair.a, &pair.b]);
+
+    fs::write(&pair.a, "edited\n").unwrap();
+    assert!(wait_until(PATIENCE, || fs::read_to_string(&target).unwrap() == "edited\n"));
+    assert!(fs::symlink_metadata(&pair.b).unwrap().file_type().is_symlink());
+}
+
+#[cfg(unix)]
+#[test]
+fn dereference_on_write_false_replaces_the_link() {
+    let pair = Pair::new("linked\n", "linked\n");
+    let target = link_b(&pair);
+    let _iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--dereference-on-write".as_ref(), "false".as_ref()]);
+
+    assert!(fs::symlink_metadata(&pair.b).unwrap().file_type().is_file());
+    assert_eq!(pair.read_b(), "linked\n");
+    assert_eq!(fs::read_to_string(pair.b.with_file_name("notes_backup.txt")).unwrap(), "linked\n");
+
+    fs::write(&pair.a, "edited\n").unwrap();
+    assert!(wait_until(PATIENCE, || pair.read_b() == "edited\n"));
+    assert!(fs::symlink_metadata(&pair.b).unwrap().file_type().is_file());
+    assert_eq!(fs::read_to_string(&target).unwrap(), "linked\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn no_follow_symlinks_keeps_the_link_and_writes_its_target() {
+    let pair = Pair::new("linked\n", "");
+    let target = link_b(&pair);
+    let _iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--no-follow-symlinks".as_ref()]);
+
+    // Seeded through the link at startup.
+    assert_eq!(fs::read_to_string(&target).unwrap(), "linked\n");
+
+    fs::write(&pair.a, "edited\n").unwrap();
+    assert!(wait_until(PATIENCE, || fs::read_to_string(&target).unwrap() == "edited\n"));
+    assert!(fs::symlink_metadata(&pair.b).unwrap().file_type().is_symlink());
+}
+
+#[test]
+fn sync_error_is_logged_and_watching_carries_on() {
+    let pair = Pair::new("start\n", "start\n");
+    let mut iyr = Watcher::start([&pair.a, &pair.b]);
+    common::block_writes(&pair.b);
+
+    fs::write(&pair.a, "lost\n").unwrap();
+    iyr.wait_for_log("Error writing B");
+    assert!(iyr.is_running());
+
+    fs::remove_dir(pair.b.with_file_name(".notes.txt.iyr-tmp")).unwrap();
+    fs::write(&pair.a, "synced\n").unwrap();
+    assert!(wait_until(PATIENCE, || pair.read_b() == "synced\n"));
+}
+
+#[test]
+fn fail_fast_exits_4_on_a_sync_error() {
+    let pair = Pair::new("start\n", "start\n");
+    let mut iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--fail-fast".as_ref()]);
+    common::block_writes(&pair.b);
+
+    fs::write(&pair.a, "lost\n").unwrap();
+    let status = iyr.wait_exit().expect("iyr kept running");
+    assert_eq!(status.code(), Some(4));
+    assert_eq!(pair.read_b(), "start\n");
+}
+
+#[test]
+fn read_only_peer_edits_never_reach_a() {
+    let pair = Pair::new("source\n", "source\n");
+    let iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--read-only-peer".as_ref(), "alert".as_ref()]);
+
+    // Emptied first, so B goes through settling before the rewrite lands:
+    // the path where B was once handed on to the A-bound sync as well.
+    fs::File::create(&pair.b).unwrap();
+    sleep(Duration::from_millis(common::DEBOUNCE_MS * 3 / 2));
+    fs::write(&pair.b, "edited on the peer\n").unwrap();
+    iyr.wait_for_log("read-only peer");
+    settle();
+    assert_eq!(pair.read_a(), "source\n");
+}
+
+#[test]
+fn read_only_peer_revert_copies_a_back() {
+    let pair = Pair::new("source\n", "source\n");
+    let _iyr = Watcher::start([pair.a.as_os_str(), pair.b.as_os_str(), "--read-only-peer".as_ref(), "revert".as_ref()]);
+
+    fs::write(&pair.b, "edited on the peer\n").unwrap();
+    assert!(wait_until(PATIENCE, || pair.read_b() == "source\n"));
+    assert_eq!(pair.read_a(), "source\n");
+}